@@ -0,0 +1,76 @@
+//! U256 micro-benchmarks: limb arithmetic against the previous byte-wise
+//! implementation. Run with `cargo bench --bench u256`.
+
+use pevm_opt_2::U256;
+use std::hint::black_box;
+use std::time::Instant;
+
+const ITERATIONS: u64 = 5_000_000;
+
+fn bytewise_add(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let mut result = [0u8; 32];
+    let mut carry = 0u16;
+    for i in (0..32).rev() {
+        let sum = a[i] as u16 + b[i] as u16 + carry;
+        result[i] = (sum & 0xFF) as u8;
+        carry = sum >> 8;
+    }
+    result
+}
+
+fn bytewise_sub(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let mut result = [0u8; 32];
+    let mut borrow = 0i16;
+    for i in (0..32).rev() {
+        let diff = a[i] as i16 - b[i] as i16 - borrow;
+        if diff < 0 {
+            result[i] = (diff + 256) as u8;
+            borrow = 1;
+        } else {
+            result[i] = diff as u8;
+            borrow = 0;
+        }
+    }
+    result
+}
+
+fn bench(name: &str, mut f: impl FnMut(u64)) -> f64 {
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        f(i);
+    }
+    let ns = start.elapsed().as_secs_f64() * 1e9 / ITERATIONS as f64;
+    println!("{:<16} {:>8.2} ns/op", name, ns);
+    ns
+}
+
+fn main() {
+    let a = U256::MAX.sub(&U256::from_u64(12345));
+    let b = U256::from_u64(0xdead_beef);
+    let (a_bytes, b_bytes) = (a.to_be_bytes(), b.to_be_bytes());
+
+    let byte_add = bench("bytewise add", |_| {
+        black_box(bytewise_add(black_box(&a_bytes), black_box(&b_bytes)));
+    });
+    let limb_add = bench("limb add", |_| {
+        black_box(black_box(a).add(black_box(&b)));
+    });
+    let byte_sub = bench("bytewise sub", |_| {
+        black_box(bytewise_sub(black_box(&a_bytes), black_box(&b_bytes)));
+    });
+    let limb_sub = bench("limb sub", |_| {
+        black_box(black_box(a).sub(black_box(&b)));
+    });
+    bench("limb mul", |_| {
+        black_box(black_box(a).mul(black_box(&b)));
+    });
+    bench("limb div", |_| {
+        black_box(black_box(a).div(black_box(&b)));
+    });
+
+    println!(
+        "add speedup {:.2}x, sub speedup {:.2}x",
+        byte_add / limb_add,
+        byte_sub / limb_sub
+    );
+}
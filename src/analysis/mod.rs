@@ -0,0 +1,222 @@
+use crate::scheduler::{AccessOracle, ConflictGraph};
+use crate::types::{AccessSets, Block, Key};
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadBound {
+    pub threads: usize,
+    pub max_speedup: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyHotness {
+    pub key: Key,
+    pub txs: usize,
+}
+
+/// Structural parallelism of a block, computed from access sets only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockAnalysis {
+    pub transactions: usize,
+    pub conflict_edges: usize,
+    /// Longest chain of conflicting transactions in block order.
+    pub critical_path: usize,
+    /// `transactions / critical_path`: the speedup with unlimited threads.
+    pub avg_parallelism: f64,
+    pub speedup_bounds: Vec<ThreadBound>,
+    pub distinct_keys: usize,
+    /// `(bucket upper bound, keys)` where the bucket counts keys touched by
+    /// `(previous bound, bound]` transactions. Bounds are powers of two.
+    pub key_hotness_histogram: Vec<(usize, usize)>,
+    pub hottest_keys: Vec<KeyHotness>,
+    pub components: usize,
+    pub largest_component: usize,
+    pub component_sizes: Vec<usize>,
+}
+
+pub struct BlockAnalyzer {
+    oracle: Box<dyn AccessOracle>,
+    top_keys: usize,
+}
+
+impl BlockAnalyzer {
+    pub fn new(oracle: Box<dyn AccessOracle>) -> Self {
+        Self {
+            oracle,
+            top_keys: 10,
+        }
+    }
+
+    pub fn with_top_keys(mut self, top_keys: usize) -> Self {
+        self.top_keys = top_keys;
+        self
+    }
+
+    pub fn analyze(&self, block: &Block, threads: &[usize]) -> BlockAnalysis {
+        let access_sets: Vec<(u64, AccessSets)> = block
+            .transactions
+            .iter()
+            .map(|tx| (tx.id, self.oracle.estimate_access_sets(tx)))
+            .collect();
+        let graph = ConflictGraph::build(&access_sets);
+
+        let n = block.transactions.len();
+        let critical_path = Self::critical_path(block, &graph);
+        let speedup_bounds = threads
+            .iter()
+            .map(|&threads| ThreadBound {
+                threads,
+                max_speedup: Self::speedup_bound(n, critical_path, threads),
+            })
+            .collect();
+
+        let mut hotness: AHashMap<Key, usize> = AHashMap::new();
+        for (_, sets) in &access_sets {
+            for key in sets.reads.union(&sets.writes) {
+                *hotness.entry(*key).or_default() += 1;
+            }
+        }
+        let mut histogram: Vec<(usize, usize)> = Vec::new();
+        for &count in hotness.values() {
+            let bound = count.next_power_of_two();
+            match histogram.iter_mut().find(|(b, _)| *b == bound) {
+                Some((_, keys)) => *keys += 1,
+                None => histogram.push((bound, 1)),
+            }
+        }
+        histogram.sort_unstable();
+        let mut hottest: Vec<KeyHotness> = hotness
+            .iter()
+            .map(|(key, &txs)| KeyHotness { key: *key, txs })
+            .collect();
+        hottest.sort_unstable_by(|a, b| {
+            b.txs
+                .cmp(&a.txs)
+                .then_with(|| (a.key.address, a.key.slot).cmp(&(b.key.address, b.key.slot)))
+        });
+        hottest.truncate(self.top_keys);
+
+        let component_sizes: Vec<usize> = graph
+            .connected_components()
+            .iter()
+            .map(|c| c.len())
+            .collect();
+
+        let analysis = BlockAnalysis {
+            transactions: n,
+            conflict_edges: graph.edge_count(),
+            critical_path,
+            avg_parallelism: if critical_path > 0 {
+                n as f64 / critical_path as f64
+            } else {
+                0.0
+            },
+            speedup_bounds,
+            distinct_keys: hotness.len(),
+            key_hotness_histogram: histogram,
+            hottest_keys: hottest,
+            components: component_sizes.len(),
+            largest_component: component_sizes.first().copied().unwrap_or(0),
+            component_sizes,
+        };
+
+        info!(
+            "Analyzed block {}: {} txs, {} edges, critical path {}, {} components",
+            block.number,
+            analysis.transactions,
+            analysis.conflict_edges,
+            analysis.critical_path,
+            analysis.components
+        );
+        analysis
+    }
+
+    /// Longest path in the DAG that orders each conflicting pair by block
+    /// position, counted in transactions.
+    fn critical_path(block: &Block, graph: &ConflictGraph) -> usize {
+        let position: AHashMap<u64, usize> = block
+            .transactions
+            .iter()
+            .enumerate()
+            .map(|(idx, tx)| (tx.id, idx))
+            .collect();
+        let mut depth = vec![0usize; block.transactions.len()];
+
+        for (idx, tx) in block.transactions.iter().enumerate() {
+            depth[idx] = 1 + graph
+                .neighbors(tx.id)
+                .iter()
+                .filter_map(|id| position.get(id))
+                .filter(|&&other| other < idx)
+                .map(|&other| depth[other])
+                .max()
+                .unwrap_or(0);
+        }
+        depth.into_iter().max().unwrap_or(0)
+    }
+
+    /// Brent's bound: with `threads` workers no schedule beats
+    /// `max(critical_path, n / threads)` steps.
+    fn speedup_bound(n: usize, critical_path: usize, threads: usize) -> f64 {
+        if n == 0 || threads == 0 {
+            return 0.0;
+        }
+        let steps = (critical_path as f64).max(n as f64 / threads as f64);
+        n as f64 / steps
+    }
+
+    pub fn export_json(&self, analysis: &BlockAnalysis, path: &str) -> Result<(), std::io::Error> {
+        let json = serde_json::to_string_pretty(analysis)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    pub fn print_analysis(&self, analysis: &BlockAnalysis) {
+        info!("\nBlock Analysis:");
+        info!("  Transactions: {}", analysis.transactions);
+        info!("  Conflict Edges: {}", analysis.conflict_edges);
+        info!("  Critical Path: {}", analysis.critical_path);
+        info!("  Avg Parallelism: {:.2}", analysis.avg_parallelism);
+        for bound in &analysis.speedup_bounds {
+            info!(
+                "  Max Speedup @{} threads: {:.2}x",
+                bound.threads, bound.max_speedup
+            );
+        }
+        info!("  Distinct Keys: {}", analysis.distinct_keys);
+        for (bound, keys) in &analysis.key_hotness_histogram {
+            info!("  Keys touched by <={} txs: {}", bound, keys);
+        }
+        for hot in analysis.hottest_keys.iter().take(5) {
+            info!("  Hot key {}: {} txs", hot.key, hot.txs);
+        }
+        info!(
+            "  Components: {} (largest {})",
+            analysis.components, analysis.largest_component
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::BlockGenerator;
+    use crate::scheduler::ExactOracle;
+
+    #[test]
+    fn test_block_analysis() {
+        let analyzer = BlockAnalyzer::new(Box::new(ExactOracle));
+
+        let free = analyzer.analyze(&BlockGenerator::no_conflicts(40, 1).generate(), &[4]);
+        assert_eq!(free.critical_path, 1);
+        assert_eq!(free.components, 40);
+        assert_eq!(free.speedup_bounds[0].max_speedup, 4.0);
+
+        let serial = analyzer.analyze(&BlockGenerator::full_conflicts(40, 1).generate(), &[4]);
+        assert_eq!(serial.critical_path, 40);
+        assert_eq!(serial.largest_component, 40);
+        assert_eq!(serial.speedup_bounds[0].max_speedup, 1.0);
+    }
+}
@@ -1,8 +1,12 @@
-use crate::evm::execute_serial;
+use crate::analysis::BlockAnalyzer;
+use crate::evm::{execute_serial_with_schedule, GasSchedule};
 use crate::generator::BlockGenerator;
 use crate::metrics::MetricsCollector;
-use crate::scheduler::{AccessListBuilder, HeuristicOracle, MIScheduler, ParallelExecutor};
-use crate::storage::{KVStore, MemoryStore};
+use crate::scheduler::{
+    export_conflicts_csv, export_conflicts_json, AccessListBuilder, AccessOracle, ConflictGraph,
+    ExactOracle, HeuristicOracle, MIScheduler, ParallelExecutor,
+};
+use crate::storage::KVStore;
 use crate::types::Block;
 use clap::{Parser, Subcommand};
 use std::time::Instant;
@@ -63,6 +67,23 @@ pub enum Commands {
         #[arg(long, default_value = "42")]
         seed: u64,
 
+        #[arg(long, default_value = "0.0")]
+        branch_ratio: f64,
+
+        #[arg(long, default_value = "0.0")]
+        rmw_ratio: f64,
+
+        #[arg(long, default_value = "0.0")]
+        dynamic_ratio: f64,
+
+        /// Reject transactions once their gas limits no longer fit (default: unlimited)
+        #[arg(long)]
+        block_gas_limit: Option<u64>,
+
+        /// EIP-4844 excess blob gas of the block header
+        #[arg(long, default_value = "0")]
+        excess_blob_gas: u64,
+
         #[arg(long, default_value = "block.json")]
         output: String,
     },
@@ -73,6 +94,10 @@ pub enum Commands {
 
         #[arg(long, default_value = "parallel")]
         mode: String, // "serial" | "parallel"
+
+        /// Hardfork preset ("berlin" | "london" | "cancun") or a JSON schedule file
+        #[arg(long, default_value = "cancun")]
+        gas_schedule: String,
     },
 
     Benchmark {
@@ -84,6 +109,41 @@ pub enum Commands {
 
         #[arg(long, default_value = "results.json")]
         output: String,
+
+        /// Write per-round conflict reports (.csv, otherwise JSON)
+        #[arg(long)]
+        conflict_report: Option<String>,
+
+        /// Hardfork preset ("berlin" | "london" | "cancun") or a JSON schedule file
+        #[arg(long, default_value = "cancun")]
+        gas_schedule: String,
+    },
+
+    /// Export the predicted conflict graph of a block, colored by MIS wave
+    Graph {
+        #[arg(long)]
+        input: String,
+
+        #[arg(long)]
+        dot: Option<String>,
+
+        #[arg(long)]
+        graphml: Option<String>,
+    },
+
+    /// Report structural parallelism of a block without executing it
+    Analyze {
+        #[arg(long)]
+        input: String,
+
+        #[arg(long, default_value = "exact")]
+        oracle: String, // "exact" | "heuristic"
+
+        #[arg(long, value_delimiter = ',', default_value = "1,2,4,8,16")]
+        threads: Vec<usize>,
+
+        #[arg(long)]
+        output: Option<String>,
     },
 }
 
@@ -95,29 +155,65 @@ pub fn handle_command(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
             conflict_ratio,
             cold_ratio,
             seed,
+            branch_ratio,
+            rmw_ratio,
+            dynamic_ratio,
+            block_gas_limit,
+            excess_blob_gas,
             output,
-        } => handle_generate(n_tx, key_space, conflict_ratio, cold_ratio, seed, &output),
+        } => {
+            let generator = BlockGenerator::new(n_tx, key_space, conflict_ratio, cold_ratio, seed)
+                .with_branch_ratio(branch_ratio)
+                .with_rmw_ratio(rmw_ratio)
+                .with_dynamic_ratio(dynamic_ratio);
+            handle_generate(generator, block_gas_limit, excess_blob_gas, &output)
+        }
 
-        Commands::Execute { input, mode } => handle_execute(&input, &mode),
+        Commands::Execute {
+            input,
+            mode,
+            gas_schedule,
+        } => handle_execute(&input, &mode, &GasSchedule::load(&gas_schedule)?),
 
         Commands::Benchmark {
             input,
             preset,
             output,
-        } => handle_benchmark(input, preset, &output),
+            conflict_report,
+            gas_schedule,
+        } => handle_benchmark(
+            input,
+            preset,
+            &output,
+            conflict_report.as_deref(),
+            &GasSchedule::load(&gas_schedule)?,
+        ),
+
+        Commands::Graph {
+            input,
+            dot,
+            graphml,
+        } => handle_graph(&input, dot.as_deref(), graphml.as_deref()),
+
+        Commands::Analyze {
+            input,
+            oracle,
+            threads,
+            output,
+        } => handle_analyze(&input, &oracle, &threads, output.as_deref()),
     }
 }
 
 fn handle_generate(
-    n_tx: usize,
-    key_space: usize,
-    conflict_ratio: f64,
-    cold_ratio: f64,
-    seed: u64,
+    generator: BlockGenerator,
+    block_gas_limit: Option<u64>,
+    excess_blob_gas: u64,
     output: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let generator = BlockGenerator::new(n_tx, key_space, conflict_ratio, cold_ratio, seed);
-    let block = generator.generate();
+    let mut block = generator.generate().with_excess_blob_gas(excess_blob_gas);
+    if let Some(gas_limit) = block_gas_limit {
+        block = block.with_gas_limit(gas_limit);
+    }
 
     let json = serde_json::to_string_pretty(&block)?;
     std::fs::write(output, json)?;
@@ -130,15 +226,20 @@ fn handle_generate(
     Ok(())
 }
 
-fn handle_execute(input: &str, mode: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn handle_execute(
+    input: &str,
+    mode: &str,
+    schedule: &GasSchedule,
+) -> Result<(), Box<dyn std::error::Error>> {
     let json = std::fs::read_to_string(input)?;
     let block: Block = serde_json::from_str(&json)?;
-    let storage = MemoryStore::new();
+    // There is no state to load, so senders start out funded.
+    let storage = BlockGenerator::funded_storage(&block);
 
     match mode {
         "serial" => {
             let start = Instant::now();
-            let result = execute_serial(&block, storage);
+            let result = execute_serial_with_schedule(&block, storage, schedule);
             let elapsed = start.elapsed().as_secs_f64() * 1000.0;
 
             info!(
@@ -152,7 +253,8 @@ fn handle_execute(input: &str, mode: &str) -> Result<(), Box<dyn std::error::Err
         "parallel" => {
             let scheduler = MIScheduler::new(10000);
             let access_builder = AccessListBuilder::new(Box::new(HeuristicOracle::new()));
-            let mut executor = ParallelExecutor::new(scheduler, access_builder, storage);
+            let mut executor = ParallelExecutor::new(scheduler, access_builder, storage)
+                .with_gas_schedule(*schedule);
 
             let start = Instant::now();
             let result = executor.execute_parallel(&block);
@@ -172,10 +274,83 @@ fn handle_execute(input: &str, mode: &str) -> Result<(), Box<dyn std::error::Err
     Ok(())
 }
 
+fn handle_graph(
+    input: &str,
+    dot: Option<&str>,
+    graphml: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if dot.is_none() && graphml.is_none() {
+        return Err("Nothing to export: pass --dot and/or --graphml".into());
+    }
+
+    let json = std::fs::read_to_string(input)?;
+    let block: Block = serde_json::from_str(&json)?;
+
+    let mut access_builder = AccessListBuilder::new(Box::new(HeuristicOracle::new()));
+    for tx in &block.transactions {
+        access_builder.estimate_before_execution(tx);
+    }
+    let waves = MIScheduler::new(10000).schedule(&block, &access_builder);
+    let access_sets: Vec<_> = block
+        .transactions
+        .iter()
+        .filter_map(|tx| {
+            access_builder
+                .get_estimated(tx.id)
+                .map(|s| (tx.id, s.clone()))
+        })
+        .collect();
+    let graph = ConflictGraph::build(&access_sets);
+
+    info!(
+        "Conflict graph: {} nodes, {} edges, {} components, {} waves",
+        graph.len(),
+        graph.edge_count(),
+        graph.connected_components().len(),
+        waves.len()
+    );
+
+    if let Some(path) = dot {
+        std::fs::write(path, graph.to_dot(&waves))?;
+        info!("DOT written to {}", path);
+    }
+    if let Some(path) = graphml {
+        std::fs::write(path, graph.to_graphml(&waves))?;
+        info!("GraphML written to {}", path);
+    }
+    Ok(())
+}
+
+fn handle_analyze(
+    input: &str,
+    oracle: &str,
+    threads: &[usize],
+    output: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let json = std::fs::read_to_string(input)?;
+    let block: Block = serde_json::from_str(&json)?;
+
+    let oracle: Box<dyn AccessOracle> = match oracle {
+        "exact" => Box::new(ExactOracle),
+        "heuristic" => Box::new(HeuristicOracle::new()),
+        _ => return Err(format!("Unknown oracle: {}", oracle).into()),
+    };
+
+    let analyzer = BlockAnalyzer::new(oracle);
+    let analysis = analyzer.analyze(&block, threads);
+    analyzer.print_analysis(&analysis);
+    if let Some(path) = output {
+        analyzer.export_json(&analysis, path)?;
+    }
+    Ok(())
+}
+
 fn handle_benchmark(
     input: Option<String>,
     preset: Option<String>,
     output: &str,
+    conflict_report: Option<&str>,
+    schedule: &GasSchedule,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let block = if let Some(input_path) = input {
         let json = std::fs::read_to_string(&input_path)?;
@@ -192,15 +367,16 @@ fn handle_benchmark(
         BlockGenerator::medium().generate()
     };
 
-    let storage1 = MemoryStore::new();
+    let storage1 = BlockGenerator::funded_storage(&block);
     let start = Instant::now();
-    let serial_result = execute_serial(&block, storage1);
+    let serial_result = execute_serial_with_schedule(&block, storage1, schedule);
     let serial_time_ms = start.elapsed().as_secs_f64() * 1000.0;
 
-    let storage2 = MemoryStore::new();
+    let storage2 = BlockGenerator::funded_storage(&block);
     let scheduler = MIScheduler::new(10000);
     let access_builder = AccessListBuilder::new(Box::new(HeuristicOracle::new()));
-    let mut executor = ParallelExecutor::new(scheduler, access_builder, storage2);
+    let mut executor =
+        ParallelExecutor::new(scheduler, access_builder, storage2).with_gas_schedule(*schedule);
 
     let start = Instant::now();
     let parallel_result = executor.execute_parallel(&block);
@@ -223,5 +399,14 @@ fn handle_benchmark(
     collector.print_metrics(&metrics);
     collector.export_json(&metrics, output)?;
 
+    if let Some(path) = conflict_report {
+        if path.ends_with(".csv") {
+            export_conflicts_csv(&parallel_result.conflict_reports, path)?;
+        } else {
+            export_conflicts_json(&parallel_result.conflict_reports, path)?;
+        }
+        info!("Conflict report written to {}", path);
+    }
+
     Ok(())
 }
@@ -0,0 +1,440 @@
+use crate::evm::context::{ExecutionContext, Frame, CALL_DEPTH_LIMIT};
+use crate::evm::gas::calculate_account_access_gas;
+use crate::evm::ops::hash_word;
+use crate::evm::{run_code, Exit};
+use crate::storage::{Code, KVStore};
+use crate::types::{AccountField, ExecutionError, Key, MicroOp, U256};
+use std::sync::Arc;
+
+/// Serialized form of `code`, which its hash and size are taken over.
+fn encode_code(code: &[MicroOp]) -> Vec<u8> {
+    serde_json::to_vec(code).expect("micro-ops always serialize")
+}
+
+/// Hash under which `code` is stored. Zero is left to accounts without
+/// code.
+pub fn code_hash(code: &[MicroOp]) -> U256 {
+    hash_word(&encode_code(code))
+}
+
+fn to_address(word: &U256) -> [u8; 20] {
+    Key::from_words(word, &U256::ZERO).address
+}
+
+/// `keccak(rlp([sender, nonce]))[12..]`
+pub fn create_address(sender: &[u8; 20], nonce: u64) -> [u8; 20] {
+    let nonce_bytes = nonce.to_be_bytes();
+    let nonce_bytes = &nonce_bytes[nonce.leading_zeros() as usize / 8..];
+    let mut payload = vec![0x80 + 20];
+    payload.extend_from_slice(sender);
+    match nonce_bytes {
+        [byte] if *byte < 0x80 => payload.push(*byte),
+        bytes => {
+            payload.push(0x80 + bytes.len() as u8);
+            payload.extend_from_slice(bytes);
+        }
+    }
+    let mut rlp = vec![0xc0 + payload.len() as u8];
+    rlp.extend(payload);
+    to_address(&hash_word(&rlp))
+}
+
+/// `keccak(0xff . sender . salt . code_hash)[12..]` (EIP-1014)
+pub fn create2_address(sender: &[u8; 20], salt: &U256, code_hash: &U256) -> [u8; 20] {
+    let mut data = vec![0xff];
+    data.extend_from_slice(sender);
+    data.extend_from_slice(&salt.to_be_bytes());
+    data.extend_from_slice(&code_hash.to_be_bytes());
+    to_address(&hash_word(&data))
+}
+
+/// Installs `code` at `address` outside of any transaction, as a genesis
+/// state would.
+pub fn deploy_code<S: KVStore>(storage: &mut S, address: [u8; 20], code: Vec<MicroOp>) {
+    let hash = code_hash(&code);
+    storage.insert_code(hash, Arc::new(code));
+    storage.set(Key::account(address, AccountField::CodeHash), hash);
+}
+
+/// Code at `address`, empty if there is none. The code hash is read
+/// through the access sets, as a deployment may change it.
+pub fn load_code<S: KVStore>(address: [u8; 20], ctx: &mut ExecutionContext<S>) -> Code {
+    let hash = ctx.read(Key::account(address, AccountField::CodeHash));
+    if hash.is_zero() {
+        return Code::default();
+    }
+    ctx.storage.code(&hash).unwrap_or_default()
+}
+
+/// Charges the EIP-2929 cost of touching `address` and warms it. An
+/// account is warm when its balance key is.
+pub fn access_account<S: KVStore>(
+    address: [u8; 20],
+    ctx: &mut ExecutionContext<S>,
+) -> Result<(), ExecutionError> {
+    let key = Key::account(address, AccountField::Balance);
+    let is_cold = !ctx.is_warm(&key);
+    ctx.consume_gas(calculate_account_access_gas(&ctx.schedule, is_cold))?;
+    if is_cold {
+        ctx.cold_keys.insert(key);
+        ctx.warm_keys.insert(key);
+    }
+    Ok(())
+}
+
+/// Moves `amount` between two balances through the journal. Returns false,
+/// moving nothing, when `from` cannot cover it.
+pub fn move_value<S: KVStore>(
+    from: [u8; 20],
+    to: [u8; 20],
+    amount: U256,
+    ctx: &mut ExecutionContext<S>,
+) -> bool {
+    if amount.is_zero() {
+        return true;
+    }
+    let from_key = Key::account(from, AccountField::Balance);
+    let balance = ctx.read(from_key);
+    if balance < amount {
+        return false;
+    }
+    ctx.write(from_key, balance.sub(&amount));
+    let to_key = Key::account(to, AccountField::Balance);
+    let credited = ctx.read(to_key).add(&amount);
+    ctx.write(to_key, credited);
+    true
+}
+
+/// `Call` and `StaticCall`. The callee runs in a fresh frame with at most
+/// all but one 64th of the remaining gas (EIP-150), and everything it wrote
+/// is undone unless it stops normally. Failing calls push 0 rather than
+/// halting the caller.
+pub fn execute_call<S: KVStore>(
+    is_static: bool,
+    ctx: &mut ExecutionContext<S>,
+) -> Result<(), ExecutionError> {
+    let op = if is_static { "STATICCALL" } else { "CALL" };
+    let gas = ctx.pop(op)?;
+    let address = ctx.pop(op)?;
+    let value = if is_static { U256::ZERO } else { ctx.pop(op)? };
+    let args_offset = ctx.pop(op)?;
+    let args_size = ctx.pop(op)?;
+    let address = to_address(&address);
+
+    if !value.is_zero() {
+        ctx.check_writable(op)?;
+        ctx.consume_gas(ctx.schedule.call_value)?;
+    }
+    access_account(address, ctx)?;
+    let size = args_size.to_u64().ok_or(ExecutionError::MemoryOutOfRange)?;
+    let offset = ctx.expand_memory(&args_offset, size)?;
+    let calldata = if size == 0 {
+        Vec::new()
+    } else {
+        ctx.memory[offset..offset + size as usize].to_vec()
+    };
+
+    if ctx.depth >= CALL_DEPTH_LIMIT {
+        return ctx.push(U256::ZERO);
+    }
+    let available = ctx.gas_limit - ctx.gas_used;
+    let forwarded = gas
+        .to_u64()
+        .unwrap_or(u64::MAX)
+        .min(available - available / 64);
+    ctx.consume_gas(forwarded)?;
+
+    let checkpoint = ctx.checkpoint();
+    if !move_value(ctx.address, address, value, ctx) {
+        ctx.gas_used -= forwarded;
+        return ctx.push(U256::ZERO);
+    }
+    let code = load_code(address, ctx);
+    let callee = Frame {
+        gas_limit: forwarded,
+        caller: ctx.address,
+        address,
+        calldata,
+        is_static: ctx.is_static || is_static,
+        ..Frame::default()
+    };
+    let caller = ctx.swap_frame(callee);
+    ctx.depth += 1;
+    let exit = run_code(&code, ctx);
+    ctx.depth -= 1;
+    let callee = ctx.swap_frame(caller);
+
+    let leftover = forwarded.saturating_sub(callee.gas_used);
+    let (success, leftover) = match exit {
+        Exit::Stop => (true, leftover),
+        Exit::Revert(_) => (false, leftover),
+        // An exceptional halt burns all of the forwarded gas.
+        Exit::Halt(..) => (false, 0),
+    };
+    if !success {
+        ctx.revert_to(checkpoint);
+    }
+    ctx.gas_used -= leftover;
+    ctx.push(success.into())
+}
+
+/// `Create` and `Create2`. There is no constructor: `code` is deployed as
+/// is. The creator nonce goes up even if the deployment then fails on an
+/// address collision. A transaction sender creating from its own program
+/// has paid its nonce already, so the address comes from the transaction
+/// nonce and a second such deployment collides. A new account has all of its fields written, which
+/// is what orders it against anything else touching that address.
+pub fn execute_create<S: KVStore>(
+    code: &[MicroOp],
+    salted: bool,
+    ctx: &mut ExecutionContext<S>,
+) -> Result<(), ExecutionError> {
+    let op = if salted { "CREATE2" } else { "CREATE" };
+    ctx.check_writable(op)?;
+    let value = ctx.pop(op)?;
+    let salt = if salted { Some(ctx.pop(op)?) } else { None };
+
+    let encoded = encode_code(code);
+    let size = encoded.len() as u64;
+    let mut gas = ctx.schedule.create + ctx.schedule.code_deposit * size;
+    if salted {
+        gas += ctx.schedule.keccak_word * size.div_ceil(32);
+    }
+    ctx.consume_gas(gas)?;
+    if ctx.depth >= CALL_DEPTH_LIMIT {
+        return ctx.push(U256::ZERO);
+    }
+
+    let creator = ctx.address;
+    if !value.is_zero() {
+        let balance = ctx.read(Key::account(creator, AccountField::Balance));
+        if balance < value {
+            return ctx.push(U256::ZERO);
+        }
+    }
+    let nonce = match ctx.tx_nonce {
+        Some(nonce) if ctx.depth == 0 && creator == ctx.caller => U256::from_u64(nonce),
+        _ => {
+            let nonce_key = Key::account(creator, AccountField::Nonce);
+            let nonce = ctx.read(nonce_key);
+            ctx.write(nonce_key, nonce.add(&U256::ONE));
+            nonce
+        }
+    };
+
+    let hash = hash_word(&encoded);
+    let address = match salt {
+        Some(salt) => create2_address(&creator, &salt, &hash),
+        None => create_address(&creator, nonce.to_u64().unwrap_or(u64::MAX)),
+    };
+    let [balance_key, new_nonce_key, code_key] = [
+        AccountField::Balance,
+        AccountField::Nonce,
+        AccountField::CodeHash,
+    ]
+    .map(|field| Key::account(address, field));
+    ctx.warm_up(balance_key);
+    if !ctx.read(new_nonce_key).is_zero() || !ctx.read(code_key).is_zero() {
+        return ctx.push(U256::ZERO);
+    }
+
+    ctx.storage.insert_code(hash, Arc::new(code.to_vec()));
+    ctx.write(new_nonce_key, U256::ONE);
+    ctx.write(code_key, hash);
+    let balance = ctx.read(balance_key);
+    ctx.write(balance_key, balance);
+    move_value(creator, address, value, ctx);
+    ctx.push(U256::from_address(&address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::execute_op;
+    use crate::storage::MemoryStore;
+
+    /// Pushes the arguments of a value-less `Call` to `address` without
+    /// calldata: size, offset, value, address and gas.
+    fn call_args(gas: u64, address: [u8; 20]) -> Vec<MicroOp> {
+        vec![
+            MicroOp::Push(U256::ZERO),
+            MicroOp::Push(U256::ZERO),
+            MicroOp::Push(U256::ZERO),
+            MicroOp::Push(U256::from_address(&address)),
+            MicroOp::Push(U256::from_u64(gas)),
+        ]
+    }
+
+    #[test]
+    fn test_nested_calls() {
+        let (outer, inner) = ([0xa1; 20], [0xa2; 20]);
+        let (outer_key, inner_key) = (Key::new(outer, [1u8; 32]), Key::new(inner, [1u8; 32]));
+        let mut storage = MemoryStore::new();
+        deploy_code(
+            &mut storage,
+            inner,
+            vec![
+                MicroOp::SStore(inner_key, U256::from_u64(2)),
+                MicroOp::Revert,
+            ],
+        );
+        let mut outer_code = vec![MicroOp::SStore(outer_key, U256::ONE)];
+        outer_code.extend(call_args(1_000_000, inner));
+        outer_code.push(MicroOp::Call);
+        deploy_code(&mut storage, outer, outer_code);
+
+        let mut ctx = ExecutionContext::with_gas_limit(storage, 100_000);
+        for op in call_args(u64::MAX, outer) {
+            execute_op(&op, &mut ctx).unwrap();
+        }
+        execute_op(&MicroOp::Call, &mut ctx).unwrap();
+
+        // the outer call succeeds, keeping its write but not the inner one
+        assert_eq!(ctx.stack, vec![U256::ONE]);
+        assert_eq!(ctx.storage.get(&outer_key), U256::ONE);
+        assert_eq!(ctx.storage.get(&inner_key), U256::ZERO);
+        assert!(ctx.access_sets.writes.contains(&outer_key));
+        assert!(ctx.gas_used < ctx.gas_limit);
+        assert_eq!(ctx.depth, 0);
+    }
+
+    #[test]
+    fn test_call_gas_forwarding() {
+        let looper = [0xb1; 20];
+        let mut storage = MemoryStore::new();
+        // burns gas until it runs out
+        deploy_code(
+            &mut storage,
+            looper,
+            vec![MicroOp::JumpDest, MicroOp::Jump(0)],
+        );
+        let mut ctx = ExecutionContext::with_gas_limit(storage, 64_000);
+        for op in call_args(u64::MAX, looper) {
+            execute_op(&op, &mut ctx).unwrap();
+        }
+        let before_call = ctx.gas_used + ctx.schedule.cold_account_access;
+        execute_op(&MicroOp::Call, &mut ctx).unwrap();
+
+        // the callee fails, taking everything but the 64th held back
+        assert_eq!(ctx.stack, vec![U256::ZERO]);
+        let available = ctx.gas_limit - before_call;
+        assert_eq!(ctx.gas_limit - ctx.gas_used, available / 64);
+    }
+
+    #[test]
+    #[cfg(not(feature = "fast-hash"))]
+    fn test_create_addresses() {
+        let hex = |address: [u8; 20]| -> String {
+            address.iter().map(|b| format!("{:02x}", b)).collect()
+        };
+        let mut sender = [0u8; 20];
+        for (i, byte) in sender.iter_mut().enumerate() {
+            let digits = "6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0";
+            *byte = u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).unwrap();
+        }
+        assert_eq!(
+            hex(create_address(&sender, 0)),
+            "cd234a471b72ba2f1ccf0a70fcaba648a5eecd8d"
+        );
+        assert_eq!(
+            hex(create_address(&sender, 1)),
+            "343c43a37d37dff08ae8c4a11544c718abb4fcf8"
+        );
+
+        // EIP-1014 example 1: zero sender and salt, init code 0x00
+        let init_hash = hash_word(&[0u8]);
+        assert_eq!(
+            hex(create2_address(&[0u8; 20], &U256::ZERO, &init_hash)),
+            "4d1a2e2bb4f88f0250f26ffff098b0b30b26bf38"
+        );
+    }
+
+    #[test]
+    fn test_create() {
+        let creator = [0xe1; 20];
+        let key = Key::new([0xe2; 20], [0u8; 32]);
+        let code = vec![MicroOp::SStore(key, U256::from_u64(5))];
+        let mut ctx = ExecutionContext::new(MemoryStore::new());
+        ctx.address = creator;
+
+        let create = MicroOp::Create(code.clone());
+        execute_op(&MicroOp::Push(U256::ZERO), &mut ctx).unwrap();
+        execute_op(&create, &mut ctx).unwrap();
+        let created = ctx.stack.pop().unwrap();
+        assert_eq!(to_address(&created), create_address(&creator, 0));
+        let size = encode_code(&code).len() as u64;
+        let gas = &ctx.schedule;
+        assert_eq!(
+            ctx.gas_used,
+            gas.very_low + gas.create + gas.code_deposit * size
+        );
+        let account = ctx.storage.account(&to_address(&created));
+        assert_eq!((account.nonce, account.code_hash), (1, code_hash(&code)));
+        assert!(ctx
+            .access_sets
+            .writes
+            .contains(&Key::account(to_address(&created), AccountField::Balance)));
+
+        // the deployed code runs when called
+        let mut args = call_args(u64::MAX, [0u8; 20]);
+        args[3] = MicroOp::Push(created);
+        for op in &args {
+            execute_op(op, &mut ctx).unwrap();
+        }
+        execute_op(&MicroOp::Call, &mut ctx).unwrap();
+        assert_eq!(ctx.stack.pop(), Some(U256::ONE));
+        assert_eq!(ctx.storage.get(&key), U256::from_u64(5));
+
+        // a second salted deployment of the same code collides
+        let create2 = MicroOp::Create2(code);
+        for _ in 0..2 {
+            execute_op(&MicroOp::Push(U256::ONE), &mut ctx).unwrap();
+            execute_op(&MicroOp::Push(U256::ZERO), &mut ctx).unwrap();
+            execute_op(&create2, &mut ctx).unwrap();
+        }
+        assert_ne!(ctx.stack[0], U256::ZERO);
+        assert_eq!(ctx.stack[1], U256::ZERO);
+        assert_eq!(ctx.storage.account(&creator).nonce, 3);
+
+        ctx.is_static = true;
+        execute_op(&MicroOp::Push(U256::ZERO), &mut ctx).unwrap();
+        assert!(execute_op(&create, &mut ctx).is_err());
+    }
+
+    #[test]
+    fn test_static_call() {
+        let writer = [0xc1; 20];
+        let key = Key::new(writer, [1u8; 32]);
+        let mut storage = MemoryStore::new();
+        deploy_code(
+            &mut storage,
+            writer,
+            vec![
+                MicroOp::CallDataSize,
+                MicroOp::Push(U256::ZERO),
+                MicroOp::CallDataLoad,
+                MicroOp::SStoreTop(key),
+            ],
+        );
+
+        let mut ctx = ExecutionContext::new(storage);
+        ctx.memory = vec![0u8; 32];
+        ctx.memory[31] = 9;
+        let mut args = call_args(u64::MAX, writer);
+        args[0] = MicroOp::Push(U256::from_u64(32));
+        for op in &args {
+            execute_op(op, &mut ctx).unwrap();
+        }
+        execute_op(&MicroOp::Call, &mut ctx).unwrap();
+        assert_eq!(ctx.stack.pop(), Some(U256::ONE));
+        assert_eq!(ctx.storage.get(&key), U256::from_u64(9));
+
+        // the same call is not allowed to write under `StaticCall`
+        args.remove(2);
+        for op in &args {
+            execute_op(op, &mut ctx).unwrap();
+        }
+        execute_op(&MicroOp::StaticCall, &mut ctx).unwrap();
+        assert_eq!(ctx.stack, vec![U256::ZERO]);
+    }
+}
@@ -1,6 +1,62 @@
+use crate::evm::gas::{calculate_blob_base_fee, calculate_memory_gas, GasSchedule};
 use crate::storage::KVStore;
-use crate::types::{AccessSets, Key, U256};
-use ahash::AHashSet;
+use crate::types::{AccessSets, Block, ExecutionError, Key, Log, U256};
+use ahash::{AHashMap, AHashSet};
+
+pub const STACK_LIMIT: usize = 1024;
+pub const CALL_DEPTH_LIMIT: usize = 1024;
+/// Highest addressable memory byte; expansion gas makes larger memories
+/// unreachable anyway.
+pub const MEMORY_LIMIT: u64 = u32::MAX as u64;
+
+/// Block-level values that price the transactions of a block and that
+/// programs can read. None of them is state, so reading them never
+/// conflicts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockEnv {
+    pub number: u64,
+    pub timestamp: u64,
+    pub chain_id: u64,
+    pub coinbase: [u8; 20],
+    pub base_fee: U256,
+    pub blob_base_fee: U256,
+}
+
+impl BlockEnv {
+    pub fn new(block: &Block, schedule: &GasSchedule) -> Self {
+        Self {
+            number: block.number,
+            timestamp: block.timestamp,
+            chain_id: block.chain_id,
+            coinbase: block.coinbase,
+            base_fee: block.base_fee,
+            blob_base_fee: calculate_blob_base_fee(schedule, block.excess_blob_gas),
+        }
+    }
+}
+
+/// State local to a call frame. A nested call swaps it in for the caller's
+/// and back out when it returns.
+#[derive(Debug, Default)]
+pub struct Frame {
+    pub stack: Vec<U256>,
+    pub memory: Vec<u8>,
+    pub gas_limit: u64,
+    pub gas_used: u64,
+    pub caller: [u8; 20],
+    pub address: [u8; 20],
+    pub calldata: Vec<u8>,
+    pub is_static: bool,
+}
+
+/// Journal positions to return to when a call frame reverts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Checkpoint {
+    journal: usize,
+    transient_journal: usize,
+    logs: usize,
+    refund: i64,
+}
 
 pub struct ExecutionContext<S: KVStore> {
     pub storage: S,
@@ -9,7 +65,39 @@ pub struct ExecutionContext<S: KVStore> {
     pub access_sets: AccessSets,
     pub gas_used: u64,
     pub stack: Vec<U256>,
+    /// Byte-addressable, always a whole number of 32-byte words.
+    pub memory: Vec<u8>,
     pub gas_limit: u64,
+    pub schedule: GasSchedule,
+    pub env: BlockEnv,
+    /// Account that called the running frame.
+    pub caller: [u8; 20],
+    /// Account whose code is running, and whose balance it spends.
+    pub address: [u8; 20],
+    pub calldata: Vec<u8>,
+    /// Set inside a `StaticCall`, where state cannot be modified.
+    pub is_static: bool,
+    /// Keys the running transaction committed to, if it declared any.
+    pub declared: Option<AHashSet<Key>>,
+    /// First key touched outside `declared`. Stays set until the
+    /// transaction ends, so that it halts every frame up to the top.
+    pub undeclared: Option<Key>,
+    /// Nonce the running transaction was sent with, already used up by the
+    /// time its program runs.
+    pub tx_nonce: Option<u64>,
+    /// Number of frames below the running one.
+    pub depth: usize,
+    /// Previous values of written slots, newest last.
+    pub journal: Vec<(Key, U256)>,
+    /// Value of each written slot at the start of the transaction.
+    pub original: AHashMap<Key, U256>,
+    /// EIP-3529 refund counter; may dip below zero mid-transaction.
+    pub refund: i64,
+    /// EIP-1153 storage, discarded at the end of each transaction.
+    pub transient: AHashMap<Key, U256>,
+    pub transient_journal: Vec<(Key, U256)>,
+    /// Emitted so far; dropped back to a checkpoint with its writes.
+    pub logs: Vec<Log>,
 }
 
 impl<S: KVStore> ExecutionContext<S> {
@@ -21,22 +109,44 @@ impl<S: KVStore> ExecutionContext<S> {
             access_sets: AccessSets::new(),
             gas_used: 0,
             stack: Vec::new(),
+            memory: Vec::new(),
             gas_limit: u64::MAX,
+            schedule: GasSchedule::default(),
+            env: BlockEnv::default(),
+            caller: [0u8; 20],
+            address: [0u8; 20],
+            calldata: Vec::new(),
+            is_static: false,
+            declared: None,
+            undeclared: None,
+            tx_nonce: None,
+            depth: 0,
+            journal: Vec::new(),
+            original: AHashMap::new(),
+            refund: 0,
+            transient: AHashMap::new(),
+            transient_journal: Vec::new(),
+            logs: Vec::new(),
         }
     }
 
     pub fn with_gas_limit(storage: S, gas_limit: u64) -> Self {
         Self {
-            storage,
-            warm_keys: AHashSet::new(),
-            cold_keys: AHashSet::new(),
-            access_sets: AccessSets::new(),
-            gas_used: 0,
-            stack: Vec::new(),
             gas_limit,
+            ..Self::new(storage)
         }
     }
 
+    pub fn with_schedule(mut self, schedule: GasSchedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    pub fn with_env(mut self, env: BlockEnv) -> Self {
+        self.env = env;
+        self
+    }
+
     pub fn warm_up(&mut self, key: Key) {
         self.warm_keys.insert(key);
     }
@@ -51,22 +161,159 @@ impl<S: KVStore> ExecutionContext<S> {
         self.warm_keys.contains(key)
     }
 
-    pub fn check_gas(&self) -> Result<(), String> {
+    /// Reads `key` into the access sets. Gas is up to the caller.
+    pub fn read(&mut self, key: Key) -> U256 {
+        self.record_read(key);
+        self.storage.get(&key)
+    }
+
+    /// Journaled write recorded in the access sets. Gas is up to the caller.
+    pub fn write(&mut self, key: Key, value: U256) {
+        self.record_write(key);
+        self.journal.push((key, self.storage.get(&key)));
+        self.storage.set(key, value);
+    }
+
+    pub fn record_read(&mut self, key: Key) {
+        self.check_declared(key);
+        self.access_sets.add_read(key);
+    }
+
+    pub fn record_write(&mut self, key: Key) {
+        self.check_declared(key);
+        self.access_sets.add_write(key);
+    }
+
+    fn check_declared(&mut self, key: Key) {
+        if self.undeclared.is_none()
+            && self
+                .declared
+                .as_ref()
+                .is_some_and(|declared| !declared.contains(&key))
+        {
+            self.undeclared = Some(key);
+        }
+    }
+
+    pub fn push(&mut self, value: U256) -> Result<(), ExecutionError> {
+        if self.stack.len() >= STACK_LIMIT {
+            return Err(ExecutionError::StackOverflow);
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    pub fn pop(&mut self, op: &str) -> Result<U256, ExecutionError> {
+        self.stack
+            .pop()
+            .ok_or_else(|| ExecutionError::StackUnderflow(op.to_string()))
+    }
+
+    /// Restores every slot, persistent or transient, written since the
+    /// journals were last cleared, and drops the refund and logs.
+    pub fn rollback(&mut self) {
+        self.revert_to(Checkpoint::default());
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            journal: self.journal.len(),
+            transient_journal: self.transient_journal.len(),
+            logs: self.logs.len(),
+            refund: self.refund,
+        }
+    }
+
+    /// Undoes the writes and logs made since `checkpoint` and restores the
+    /// refund counter it saw.
+    pub fn revert_to(&mut self, checkpoint: Checkpoint) {
+        self.refund = checkpoint.refund;
+        self.logs.truncate(checkpoint.logs);
+        while self.journal.len() > checkpoint.journal {
+            let (key, previous) = self.journal.pop().unwrap();
+            self.storage.set(key, previous);
+        }
+        while self.transient_journal.len() > checkpoint.transient_journal {
+            let (key, previous) = self.transient_journal.pop().unwrap();
+            self.transient.insert(key, previous);
+        }
+    }
+
+    /// Makes `frame` the running one and returns the frame it replaces.
+    pub fn swap_frame(&mut self, mut frame: Frame) -> Frame {
+        std::mem::swap(&mut self.stack, &mut frame.stack);
+        std::mem::swap(&mut self.memory, &mut frame.memory);
+        std::mem::swap(&mut self.gas_limit, &mut frame.gas_limit);
+        std::mem::swap(&mut self.gas_used, &mut frame.gas_used);
+        std::mem::swap(&mut self.caller, &mut frame.caller);
+        std::mem::swap(&mut self.address, &mut frame.address);
+        std::mem::swap(&mut self.calldata, &mut frame.calldata);
+        std::mem::swap(&mut self.is_static, &mut frame.is_static);
+        frame
+    }
+
+    pub fn check_writable(&self, op: &str) -> Result<(), ExecutionError> {
+        if self.is_static {
+            return Err(ExecutionError::StaticStateChange(op.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Charges expansion gas and grows memory to cover `size` bytes at
+    /// `offset`. Returns the offset; a zero size never expands.
+    pub fn expand_memory(&mut self, offset: &U256, size: u64) -> Result<usize, ExecutionError> {
+        if size == 0 {
+            return Ok(offset.to_u64().unwrap_or(0).min(MEMORY_LIMIT) as usize);
+        }
+        let start = offset
+            .to_u64()
+            .filter(|start| start.saturating_add(size) <= MEMORY_LIMIT)
+            .ok_or(ExecutionError::MemoryOutOfRange)?;
+        let words = (start + size).div_ceil(32);
+        let current = self.memory.len() as u64 / 32;
+        if words > current {
+            let gas = &self.schedule;
+            let cost = calculate_memory_gas(gas, words) - calculate_memory_gas(gas, current);
+            self.consume_gas(cost)?;
+            self.memory.resize(words as usize * 32, 0);
+        }
+        Ok(start as usize)
+    }
+
+    pub fn tload(&self, key: &Key) -> U256 {
+        self.transient.get(key).copied().unwrap_or(U256::ZERO)
+    }
+
+    pub fn tstore(&mut self, key: Key, value: U256) {
+        let previous = self.tload(&key);
+        self.transient_journal.push((key, previous));
+        self.transient.insert(key, value);
+    }
+
+    pub fn clear_transient(&mut self) {
+        self.transient.clear();
+        self.transient_journal.clear();
+    }
+
+    pub fn check_gas(&self) -> Result<(), ExecutionError> {
         if self.gas_used > self.gas_limit {
-            Err(format!(
-                "Out of gas: used {} > limit {}",
-                self.gas_used, self.gas_limit
-            ))
+            Err(ExecutionError::OutOfGas {
+                used: self.gas_used,
+                limit: self.gas_limit,
+            })
         } else {
             Ok(())
         }
     }
 
-    pub fn consume_gas(&mut self, amount: u64) -> Result<(), String> {
+    pub fn consume_gas(&mut self, amount: u64) -> Result<(), ExecutionError> {
         self.gas_used = self
             .gas_used
             .checked_add(amount)
-            .ok_or_else(|| "Gas overflow".to_string())?;
+            .ok_or(ExecutionError::OutOfGas {
+                used: u64::MAX,
+                limit: self.gas_limit,
+            })?;
         self.check_gas()
     }
 }
@@ -84,5 +331,25 @@ mod tests {
         assert!(ctx.is_warm(&key));
         assert!(ctx.consume_gas(500).is_ok());
         assert!(ctx.consume_gas(600).is_err());
+
+        ctx.journal.push((key, U256::ZERO));
+        ctx.storage.set(key, U256::from_u64(7));
+        ctx.tstore(key, U256::from_u64(3));
+        ctx.rollback();
+        assert_eq!(ctx.storage.get(&key), U256::ZERO);
+        assert_eq!(ctx.tload(&key), U256::ZERO);
+
+        ctx.gas_used = 0;
+        assert_eq!(ctx.expand_memory(&U256::from_u64(10), 32).unwrap(), 10);
+        assert_eq!(ctx.memory.len(), 64);
+        assert_eq!(ctx.gas_used, calculate_memory_gas(&ctx.schedule, 2));
+        ctx.expand_memory(&U256::ZERO, 64).unwrap();
+        assert_eq!(ctx.gas_used, calculate_memory_gas(&ctx.schedule, 2));
+        assert!(ctx.expand_memory(&U256::MAX, 1).is_err());
+
+        for _ in 0..STACK_LIMIT {
+            ctx.push(U256::ONE).unwrap();
+        }
+        assert!(ctx.push(U256::ONE).is_err());
     }
 }
@@ -1,41 +1,299 @@
-use crate::types::U256;
-
-pub const COLD_SLOAD_COST: u64 = 2100;
-pub const WARM_SLOAD_COST: u64 = 100;
-pub const COLD_SSTORE_COST: u64 = 20000;
-pub const WARM_SSTORE_COST: u64 = 2900;
-pub const SSTORE_RESET_COST: u64 = 5000;
-pub const SSTORE_SET_COST: u64 = 20000;
-pub const ADD_COST: u64 = 3;
-pub const SUB_COST: u64 = 3;
-pub const KECCAK_BASE_COST: u64 = 30;
-pub const KECCAK_WORD_COST: u64 = 6;
-pub const NOOP_COST: u64 = 1;
-
-pub fn calculate_sload_gas(is_cold: bool) -> u64 {
+use crate::types::{BinaryOp, U256};
+use serde::{Deserialize, Serialize};
+
+/// Gas prices of one hardfork. Simple opcodes are priced by the Yellow Paper
+/// tiers: `base` (POP, MSIZE), `very_low` (ADD, SUB, comparisons, bitwise
+/// ops, shifts, PUSH, DUP, SWAP, MLOAD, MSTORE, MSTORE8), `low` (MUL, DIV,
+/// SDIV, MOD, SMOD, SIGNEXTEND), `mid` (JUMP) and `high` (JUMPI).
+///
+/// Missing fields of a JSON schedule default to Cancun.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GasSchedule {
+    /// Intrinsic cost charged to every transaction before its first op.
+    pub tx_base: u64,
+    pub base: u64,
+    pub very_low: u64,
+    pub low: u64,
+    pub mid: u64,
+    pub high: u64,
+    pub jumpdest: u64,
+    pub noop: u64,
+    pub exp_base: u64,
+    pub exp_byte: u64,
+    pub keccak_base: u64,
+    pub keccak_word: u64,
+    pub memory_word: u64,
+    pub memory_quadratic_divisor: u64,
+    pub cold_sload: u64,
+    /// Also the price of accessing a warm account.
+    pub warm_sload: u64,
+    pub cold_account_access: u64,
+    /// Surcharge for moving a non-zero value.
+    pub call_value: u64,
+    pub create: u64,
+    /// Per byte of deployed code.
+    pub code_deposit: u64,
+    /// LOG costs `log + log_topic * topics + log_data * bytes`.
+    pub log: u64,
+    pub log_topic: u64,
+    pub log_data: u64,
+    pub sstore_set: u64,
+    pub sstore_reset: u64,
+    pub sstore_clears_refund: u64,
+    /// Refunds are capped at `gas_used / max_refund_quotient`.
+    pub max_refund_quotient: u64,
+    /// Cost of TLOAD and TSTORE; `None` where EIP-1153 is not active.
+    pub transient: Option<u64>,
+    /// EIP-4844 blob gas per blob. Blob transactions are rejected while
+    /// `max_blob_gas_per_block` is zero.
+    pub blob_gas_per_blob: u64,
+    pub max_blob_gas_per_block: u64,
+    pub target_blob_gas_per_block: u64,
+    pub min_blob_base_fee: u64,
+    pub blob_base_fee_update_fraction: u64,
+}
+
+impl GasSchedule {
+    /// EIP-2929 access costs with EIP-2200 refunds.
+    pub fn berlin() -> Self {
+        Self {
+            tx_base: 21000,
+            base: 2,
+            very_low: 3,
+            low: 5,
+            mid: 8,
+            high: 10,
+            jumpdest: 1,
+            noop: 1,
+            exp_base: 10,
+            exp_byte: 50,
+            keccak_base: 30,
+            keccak_word: 6,
+            memory_word: 3,
+            memory_quadratic_divisor: 512,
+            cold_sload: 2100,
+            warm_sload: 100,
+            cold_account_access: 2600,
+            call_value: 9000,
+            create: 32000,
+            code_deposit: 200,
+            log: 375,
+            log_topic: 375,
+            log_data: 8,
+            sstore_set: 20000,
+            // EIP-2929 takes the cold surcharge out of the old 5000.
+            sstore_reset: 5000 - 2100,
+            sstore_clears_refund: 15000,
+            max_refund_quotient: 2,
+            transient: None,
+            blob_gas_per_blob: 1 << 17,
+            max_blob_gas_per_block: 0,
+            target_blob_gas_per_block: 0,
+            min_blob_base_fee: 1,
+            blob_base_fee_update_fraction: 3338477,
+        }
+    }
+
+    /// EIP-3529 reduced refunds.
+    pub fn london() -> Self {
+        Self {
+            sstore_clears_refund: 4800,
+            max_refund_quotient: 5,
+            ..Self::berlin()
+        }
+    }
+
+    /// EIP-1153 transient storage, priced as a warm access, and EIP-4844
+    /// blobs with a target of three and a maximum of six per block.
+    pub fn cancun() -> Self {
+        let london = Self::london();
+        Self {
+            transient: Some(london.warm_sload),
+            max_blob_gas_per_block: 6 * london.blob_gas_per_blob,
+            target_blob_gas_per_block: 3 * london.blob_gas_per_blob,
+            ..london
+        }
+    }
+
+    /// A preset name (`berlin`, `london`, `cancun`) or a path to a JSON
+    /// schedule.
+    pub fn load(spec: &str) -> Result<Self, String> {
+        match spec.to_ascii_lowercase().as_str() {
+            "berlin" => Ok(Self::berlin()),
+            "london" => Ok(Self::london()),
+            "cancun" => Ok(Self::cancun()),
+            _ => {
+                let json = std::fs::read_to_string(spec)
+                    .map_err(|e| format!("Failed to read gas schedule {}: {}", spec, e))?;
+                serde_json::from_str(&json)
+                    .map_err(|e| format!("Invalid gas schedule {}: {}", spec, e))
+            }
+        }
+    }
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        Self::cancun()
+    }
+}
+
+pub fn calculate_sload_gas(gas: &GasSchedule, is_cold: bool) -> u64 {
+    if is_cold {
+        gas.cold_sload
+    } else {
+        gas.warm_sload
+    }
+}
+
+pub fn calculate_account_access_gas(gas: &GasSchedule, is_cold: bool) -> u64 {
     if is_cold {
-        COLD_SLOAD_COST
+        gas.cold_account_access
     } else {
-        WARM_SLOAD_COST
+        gas.warm_sload
     }
 }
 
-pub fn calculate_sstore_gas(is_cold: bool, current: U256, new_value: U256) -> u64 {
-    let is_zero = current == U256::ZERO;
-    let new_is_zero = new_value == U256::ZERO;
+pub fn calculate_log_gas(gas: &GasSchedule, topics: usize, size: u64) -> u64 {
+    gas.log + gas.log_topic * topics as u64 + gas.log_data * size
+}
+
+/// EIP-2200 net gas metering with EIP-2929 access costs. `original` is the
+/// slot value at the start of the transaction. Returns the gas to charge and
+/// the change to the refund counter, which is negative when an earlier
+/// refund is undone.
+pub fn calculate_sstore_gas(
+    gas: &GasSchedule,
+    is_cold: bool,
+    original: &U256,
+    current: &U256,
+    new_value: &U256,
+) -> (u64, i64) {
+    let surcharge = if is_cold { gas.cold_sload } else { 0 };
+    if current == new_value {
+        return (surcharge + gas.warm_sload, 0);
+    }
+
+    let clears = gas.sstore_clears_refund as i64;
+    if original == current {
+        if original.is_zero() {
+            return (surcharge + gas.sstore_set, 0);
+        }
+        let refund = if new_value.is_zero() { clears } else { 0 };
+        return (surcharge + gas.sstore_reset, refund);
+    }
 
-    match (is_cold, is_zero, new_is_zero) {
-        (true, _, true) => SSTORE_RESET_COST,
-        (true, _, false) => COLD_SSTORE_COST,
-        (false, true, false) => SSTORE_SET_COST,
-        (false, false, true) => SSTORE_RESET_COST,
-        (false, false, false) => WARM_SSTORE_COST,
-        (false, true, true) => WARM_SSTORE_COST,
+    // Slot already dirty in this transaction.
+    let mut refund = 0;
+    if !original.is_zero() {
+        if current.is_zero() {
+            refund -= clears;
+        }
+        if new_value.is_zero() {
+            refund += clears;
+        }
+    }
+    if original == new_value {
+        refund += if original.is_zero() {
+            gas.sstore_set as i64 - gas.warm_sload as i64
+        } else {
+            gas.sstore_reset as i64 - gas.warm_sload as i64
+        };
     }
+    (surcharge + gas.warm_sload, refund)
+}
+
+/// Refund actually paid back for a transaction that used `gross_gas`.
+pub fn calculate_refund(gas: &GasSchedule, gross_gas: u64, refund_counter: i64) -> u64 {
+    (refund_counter.max(0) as u64).min(gross_gas / gas.max_refund_quotient.max(1))
+}
+
+pub fn calculate_keccak_gas(gas: &GasSchedule, data_len: usize) -> u64 {
+    gas.keccak_base + gas.keccak_word * data_len.div_ceil(32) as u64
+}
+
+/// Total cost of a memory of `words` 32-byte words. Expansion charges the
+/// difference between the new and the old total.
+pub fn calculate_memory_gas(gas: &GasSchedule, words: u64) -> u64 {
+    gas.memory_word * words + words * words / gas.memory_quadratic_divisor.max(1)
 }
 
-pub fn calculate_keccak_gas(data_len: usize) -> u64 {
-    KECCAK_BASE_COST + KECCAK_WORD_COST * data_len.div_ceil(32) as u64
+pub fn calculate_exp_gas(gas: &GasSchedule, exponent: &U256) -> u64 {
+    gas.exp_base + gas.exp_byte * exponent.byte_len() as u64
+}
+
+/// Bytes held by one EIP-4844 blob: 4096 field elements of 32 bytes.
+pub const BYTES_PER_BLOB: u64 = 4096 * 32;
+
+/// Blob gas of a transaction carrying `blob_size` bytes of blob data.
+pub fn calculate_blob_gas(gas: &GasSchedule, blob_size: u64) -> u64 {
+    blob_size.div_ceil(BYTES_PER_BLOB) * gas.blob_gas_per_blob
+}
+
+/// Price of one unit of blob gas in a block with `excess_blob_gas`.
+pub fn calculate_blob_base_fee(gas: &GasSchedule, excess_blob_gas: u64) -> U256 {
+    fake_exponential(
+        gas.min_blob_base_fee as u128,
+        excess_blob_gas as u128,
+        gas.blob_base_fee_update_fraction.max(1) as u128,
+    )
+}
+
+/// Excess blob gas of the block following one with the given excess and
+/// blob gas used.
+pub fn calculate_excess_blob_gas(
+    gas: &GasSchedule,
+    parent_excess_blob_gas: u64,
+    parent_blob_gas_used: u64,
+) -> u64 {
+    parent_excess_blob_gas
+        .saturating_add(parent_blob_gas_used)
+        .saturating_sub(gas.target_blob_gas_per_block)
+}
+
+/// `factor * e ** (numerator / denominator)` by Taylor expansion, as
+/// specified by EIP-4844. Saturates at `u128::MAX` so that multiplying by a
+/// blob gas amount cannot overflow a `U256`.
+fn fake_exponential(factor: u128, numerator: u128, denominator: u128) -> U256 {
+    let mut output: u128 = 0;
+    let mut accum = factor * denominator;
+    let mut i = 1;
+    while accum > 0 {
+        let next = output.checked_add(accum).zip(accum.checked_mul(numerator));
+        let Some((sum, product)) = next else {
+            return U256::from_u128(u128::MAX);
+        };
+        output = sum;
+        accum = product / (denominator * i);
+        i += 1;
+    }
+    U256::from_u128(output / denominator)
+}
+
+pub fn calculate_binary_gas(gas: &GasSchedule, op: BinaryOp, operand: &U256) -> u64 {
+    match op {
+        BinaryOp::Exp => calculate_exp_gas(gas, operand),
+        BinaryOp::Mul
+        | BinaryOp::Div
+        | BinaryOp::SDiv
+        | BinaryOp::Mod
+        | BinaryOp::SMod
+        | BinaryOp::SignExtend => gas.low,
+        BinaryOp::Add
+        | BinaryOp::Sub
+        | BinaryOp::Lt
+        | BinaryOp::Gt
+        | BinaryOp::SLt
+        | BinaryOp::SGt
+        | BinaryOp::Eq
+        | BinaryOp::And
+        | BinaryOp::Or
+        | BinaryOp::Xor
+        | BinaryOp::Shl
+        | BinaryOp::Shr
+        | BinaryOp::Sar => gas.very_low,
+    }
 }
 
 #[cfg(test)]
@@ -44,11 +302,116 @@ mod tests {
 
     #[test]
     fn test_gas_calculations() {
-        assert_eq!(calculate_sload_gas(true), COLD_SLOAD_COST);
-        assert_eq!(calculate_sload_gas(false), WARM_SLOAD_COST);
+        let gas = GasSchedule::cancun();
+        assert_eq!(calculate_sload_gas(&gas, true), 2100);
+        assert_eq!(calculate_sload_gas(&gas, false), 100);
+        assert_eq!(calculate_keccak_gas(&gas, 32), 36);
+        assert_eq!(calculate_refund(&gas, 10000, 4800), 2000);
+        assert_eq!(calculate_refund(&gas, 10000, -4800), 0);
+        assert_eq!(calculate_refund(&GasSchedule::berlin(), 10000, 4800), 4800);
+        assert_eq!(calculate_memory_gas(&gas, 1), 3);
+        assert_eq!(calculate_memory_gas(&gas, 1024), 3 * 1024 + 2048);
+        assert_eq!(calculate_exp_gas(&gas, &U256::ZERO), 10);
+        assert_eq!(calculate_exp_gas(&gas, &U256::from_u64(256)), 110);
+        assert_eq!(calculate_binary_gas(&gas, BinaryOp::Mul, &U256::ZERO), 5);
+        assert_eq!(GasSchedule::london().transient, None);
+        assert_eq!(gas.transient, Some(100));
+    }
+
+    #[test]
+    fn test_blob_gas() {
+        let gas = GasSchedule::cancun();
+        assert_eq!(calculate_blob_gas(&gas, 0), 0);
+        assert_eq!(calculate_blob_gas(&gas, 1), 131072);
+        assert_eq!(calculate_blob_gas(&gas, 2 * BYTES_PER_BLOB + 1), 3 * 131072);
+
+        // blob base fees from the EIP-4844 reference formula
+        assert_eq!(calculate_blob_base_fee(&gas, 0), U256::ONE);
+        assert_eq!(calculate_blob_base_fee(&gas, 3338477), U256::from_u64(2));
+        assert_eq!(
+            calculate_blob_base_fee(&gas, 10 * 3338477),
+            U256::from_u64(22026)
+        );
         assert_eq!(
-            calculate_keccak_gas(32),
-            KECCAK_BASE_COST + KECCAK_WORD_COST
+            calculate_blob_base_fee(&gas, u64::MAX),
+            U256::from_u128(u128::MAX)
         );
+
+        let target = gas.target_blob_gas_per_block;
+        assert_eq!(calculate_excess_blob_gas(&gas, 0, target), 0);
+        assert_eq!(calculate_excess_blob_gas(&gas, 0, 2 * target), target);
+        assert_eq!(calculate_excess_blob_gas(&gas, target, 0), 0);
+        assert_eq!(GasSchedule::london().max_blob_gas_per_block, 0);
+    }
+
+    #[test]
+    fn test_gas_schedule_json() {
+        let custom: GasSchedule = serde_json::from_str(r#"{"cold_sload": 5000}"#).unwrap();
+        assert_eq!(custom.cold_sload, 5000);
+        assert_eq!(custom.warm_sload, GasSchedule::cancun().warm_sload);
+
+        let path = std::env::temp_dir().join("pevm_gas_schedule_test.json");
+        std::fs::write(&path, serde_json::to_string(&custom).unwrap()).unwrap();
+        assert_eq!(GasSchedule::load(path.to_str().unwrap()).unwrap(), custom);
+        assert_eq!(GasSchedule::load("Berlin").unwrap(), GasSchedule::berlin());
+        assert!(GasSchedule::load("/nonexistent/schedule.json").is_err());
+        let _ = std::fs::remove_file(path);
+    }
+
+    /// The EIP-3529 test table: each case stores `values` in order into a
+    /// warm slot holding `original`. Gas includes two PUSH1s per SSTORE.
+    #[test]
+    fn test_sstore_eip3529_table() {
+        let cases: [(u64, &[u64], u64, i64); 17] = [
+            (0, &[0, 0], 212, 0),
+            (0, &[0, 1], 20112, 0),
+            (0, &[1, 0], 20112, 19900),
+            (0, &[1, 2], 20112, 0),
+            (0, &[1, 1], 20112, 0),
+            (1, &[0, 0], 3012, 4800),
+            (1, &[0, 1], 3012, 2800),
+            (1, &[0, 2], 3012, 0),
+            (1, &[2, 0], 3012, 4800),
+            (1, &[2, 3], 3012, 0),
+            (1, &[2, 1], 3012, 2800),
+            (1, &[2, 2], 3012, 0),
+            (1, &[1, 0], 3012, 4800),
+            (1, &[1, 2], 3012, 0),
+            (1, &[1, 1], 212, 0),
+            (0, &[1, 0, 1], 40118, 19900),
+            (1, &[0, 1, 0], 5918, 7600),
+        ];
+        let london = GasSchedule::london();
+        for (original, values, expected_gas, expected_refund) in cases {
+            assert_eq!(
+                run_sstores(&london, original, values),
+                (expected_gas, expected_refund),
+                "original {}, values {:?}",
+                original,
+                values
+            );
+        }
+
+        // Berlin still refunds 15000 per cleared slot.
+        let berlin = GasSchedule::berlin();
+        assert_eq!(run_sstores(&berlin, 1, &[0, 0]), (3012, 15000));
+        assert_eq!(run_sstores(&berlin, 1, &[0, 1, 0]), (5918, 17800));
+
+        let (cold, _) = calculate_sstore_gas(&london, true, &U256::ONE, &U256::ONE, &U256::ZERO);
+        assert_eq!(cold, london.cold_sload + london.sstore_reset);
+    }
+
+    fn run_sstores(gas: &GasSchedule, original: u64, values: &[u64]) -> (u64, i64) {
+        let original = U256::from_u64(original);
+        let mut current = original;
+        let (mut total, mut refund) = (0, 0);
+        for &value in values {
+            let value = U256::from_u64(value);
+            let (cost, delta) = calculate_sstore_gas(gas, false, &original, &current, &value);
+            total += cost + 2 * gas.very_low;
+            refund += delta;
+            current = value;
+        }
+        (total, refund)
     }
 }
@@ -1,67 +1,337 @@
+pub mod call;
 pub mod context;
 pub mod gas;
+pub mod keccak;
 pub mod ops;
+pub mod receipt;
 
-pub use context::ExecutionContext;
+pub use call::{code_hash, deploy_code};
+pub use context::{BlockEnv, ExecutionContext};
 pub use gas::*;
-pub use ops::execute_op;
+pub use ops::{execute_op, Flow};
+pub use receipt::{build_receipts, Bloom, Receipt};
 
 use crate::storage::KVStore;
-use crate::types::{Block, ExecutionResult, Transaction};
+use crate::types::{
+    count_failures, AccountField, Block, ExecutionError, ExecutionResult, Key, MicroOp,
+    Transaction, U256,
+};
+use std::collections::BTreeMap;
 use tracing::{debug, error, info};
 
 pub struct SerialExecutionResult<S: KVStore> {
     pub storage: S,
     pub results: Vec<ExecutionResult>,
+    /// One per transaction, in block order like `results`.
+    pub receipts: Vec<Receipt>,
+    /// Failed transactions per [`ExecutionError::kind`].
+    pub failures: BTreeMap<String, usize>,
     pub total_gas: u64,
+    pub blob_gas_used: u64,
 }
 
+/// Runs `tx` with its `gas_hint` as the gas limit, after charging the
+/// intrinsic cost. A transaction that cannot cover the intrinsic cost or
+/// the gas it may buy, or whose nonce is not the sender's next, is invalid
+/// and charged nothing; any other pays for its gas and blobs and bumps the
+/// sender nonce, even if it reverts.
+///
+/// Gas and blob fees are taken from the sender balance up front, and blob
+/// fees are kept even if the transaction reverts. The priority fee is left
+/// for the caller to credit to the coinbase, see [`credit_coinbase`].
 pub fn execute_transaction<S: KVStore>(
     tx: &Transaction,
     ctx: &mut ExecutionContext<S>,
 ) -> ExecutionResult {
-    debug!("Executing transaction {}", tx.id);
+    if tx.gas_hint < ctx.schedule.tx_base {
+        return ExecutionResult::failure(
+            tx.id,
+            ExecutionError::IntrinsicGas {
+                required: ctx.schedule.tx_base,
+                limit: tx.gas_hint,
+            },
+        );
+    }
+    let base_fee = ctx.env.base_fee;
+    let price = match effective_gas_price(tx, &base_fee) {
+        Ok(price) => price,
+        Err(e) => return ExecutionResult::failure(tx.id, e),
+    };
 
-    ctx.warm_up_keys(&tx.metadata.access_list);
+    let value = tx.metadata.value;
+    if !value.is_zero() && tx.metadata.to.is_none() {
+        return ExecutionResult::failure(tx.id, ExecutionError::ValueWithoutRecipient);
+    }
+
+    let nonce_key = Key::account(tx.metadata.from, AccountField::Nonce);
+    let nonce = ctx.storage.get(&nonce_key);
+    if nonce != U256::from_u64(tx.metadata.nonce) {
+        let mut result = ExecutionResult::failure(
+            tx.id,
+            ExecutionError::NonceMismatch {
+                expected: nonce.to_u64().unwrap_or(u64::MAX),
+                actual: tx.metadata.nonce,
+            },
+        );
+        result.access_sets.add_read(nonce_key);
+        return result;
+    }
+
+    // Gas and blobs are bought up front, outside the journal; whatever gas
+    // goes unused is paid back below.
+    let balance_key = Key::account(tx.metadata.from, AccountField::Balance);
+    let upfront = price.mul(&U256::from_u64(tx.gas_hint));
+    let blob_gas = calculate_blob_gas(&ctx.schedule, tx.metadata.blob_size);
+    let blob_fee = ctx.env.blob_base_fee.mul(&U256::from_u64(blob_gas));
+    let charged = upfront.add(&blob_fee);
+    if !charged.is_zero() || !value.is_zero() {
+        let balance = ctx.storage.get(&balance_key);
+        if balance < charged.add(&value) {
+            let mut result = ExecutionResult::failure(
+                tx.id,
+                ExecutionError::InsufficientBalance {
+                    balance,
+                    gas_cost: charged,
+                    value,
+                },
+            );
+            result.access_sets.add_read(balance_key);
+            return result;
+        }
+        ctx.storage.set(balance_key, balance.sub(&charged));
+    }
+
+    ctx.gas_limit = tx.gas_hint;
+    ctx.gas_used += ctx.schedule.tx_base;
+    ctx.caller = tx.metadata.from;
+    ctx.address = tx.metadata.to.unwrap_or(tx.metadata.from);
+    ctx.calldata = tx.metadata.calldata.clone();
+    ctx.tx_nonce = Some(tx.metadata.nonce);
+    ctx.warm_up(Key::account(tx.metadata.from, AccountField::Balance));
+    ctx.warm_up(Key::account(ctx.address, AccountField::Balance));
+    // Outside the journal, so a revert keeps it.
+    ctx.storage.set(nonce_key, nonce.add(&U256::ONE));
+
+    let mut result = run_program(tx, ctx);
+    ctx.clear_transient();
+    result.access_sets.add_read(nonce_key);
+    result.access_sets.add_write(nonce_key);
+    if !charged.is_zero() {
+        let unused = price.mul(&U256::from_u64(tx.gas_hint - result.gas_used));
+        let balance = ctx.storage.get(&balance_key);
+        ctx.storage.set(balance_key, balance.add(&unused));
+        result.access_sets.add_read(balance_key);
+        result.access_sets.add_write(balance_key);
+    }
+    let tip = price.sub(&base_fee).mul(&U256::from_u64(result.gas_used));
+    result
+        .with_blob_gas(blob_gas, blob_fee)
+        .with_priority_fee(tip)
+}
+
+/// EIP-1559 price per gas: the base fee plus as much of the priority fee
+/// as the fee cap leaves room for.
+pub fn effective_gas_price(tx: &Transaction, base_fee: &U256) -> Result<U256, ExecutionError> {
+    let max_fee = tx.metadata.max_fee_per_gas;
+    if max_fee < *base_fee {
+        return Err(ExecutionError::FeeCapBelowBaseFee {
+            max_fee,
+            base_fee: *base_fee,
+        });
+    }
+    let tip = tx
+        .metadata
+        .max_priority_fee_per_gas
+        .min(max_fee.sub(base_fee));
+    Ok(base_fee.add(&tip))
+}
+
+/// Pays `amount` to `coinbase` without recording an access. Serial
+/// execution credits after every transaction; the parallel executor defers
+/// credits so that fee payments do not make every transaction conflict.
+pub fn credit_coinbase<S: KVStore>(storage: &mut S, coinbase: [u8; 20], amount: &U256) {
+    if amount.is_zero() {
+        return;
+    }
+    let key = Key::account(coinbase, AccountField::Balance);
+    let balance = storage.get(&key);
+    storage.set(key, balance.add(amount));
+}
+
+/// Rejects `tx` if its gas limit or its blob gas does not fit in what is
+/// left of the block, as a block producer would.
+pub fn check_block_gas(
+    block: &Block,
+    schedule: &GasSchedule,
+    gas_used: u64,
+    blob_gas_used: u64,
+    tx: &Transaction,
+) -> Result<(), ExecutionError> {
+    let remaining = block.gas_limit.saturating_sub(gas_used);
+    if tx.gas_hint > remaining {
+        return Err(ExecutionError::BlockGasLimit {
+            gas_limit: tx.gas_hint,
+            remaining,
+        });
+    }
 
-    for (idx, op) in tx.metadata.program.iter().enumerate() {
-        match execute_op(op, ctx) {
-            Ok(()) => {}
-            Err(e) => {
-                error!("Transaction {} failed at op {}: {}", tx.id, idx, e);
-                return ExecutionResult::failure(tx.id, e);
+    let blob_gas = calculate_blob_gas(schedule, tx.metadata.blob_size);
+    if blob_gas == 0 {
+        return Ok(());
+    }
+    if schedule.max_blob_gas_per_block == 0 {
+        return Err(ExecutionError::BlobsUnsupported);
+    }
+    let remaining = schedule
+        .max_blob_gas_per_block
+        .saturating_sub(blob_gas_used);
+    if blob_gas > remaining {
+        return Err(ExecutionError::BlobGasLimit {
+            blob_gas,
+            remaining,
+        });
+    }
+    Ok(())
+}
+
+/// How a call frame ended.
+pub enum Exit {
+    Stop,
+    /// Reverted at this op.
+    Revert(usize),
+    /// Halted exceptionally at this op.
+    Halt(usize, ExecutionError),
+}
+
+/// Interprets `code` in the running frame of `ctx`.
+pub fn run_code<S: KVStore>(code: &[MicroOp], ctx: &mut ExecutionContext<S>) -> Exit {
+    let mut pc = 0;
+    while pc < code.len() {
+        let flow = execute_op(&code[pc], ctx).and_then(|flow| match flow {
+            Flow::Jump(target) if !matches!(code.get(target), Some(MicroOp::JumpDest)) => {
+                Err(ExecutionError::InvalidJump(target))
             }
+            _ => match ctx.undeclared {
+                Some(key) => Err(ExecutionError::UndeclaredAccess(key)),
+                None => Ok(flow),
+            },
+        });
+        match flow {
+            Ok(Flow::Next) => pc += 1,
+            Ok(Flow::Jump(target)) => pc = target,
+            Ok(Flow::Stop) => break,
+            Ok(Flow::Revert) => return Exit::Revert(pc),
+            Err(e) => return Exit::Halt(pc, e),
         }
     }
+    Exit::Stop
+}
 
-    ExecutionResult::success(
-        tx.id,
-        ctx.gas_used,
-        ctx.access_sets.clone(),
-        ctx.warm_keys.clone(),
-        ctx.cold_keys.clone(),
-    )
+/// Runs the code at `to`, after moving the value there, or the inline
+/// program when the transaction has no recipient.
+fn run_program<S: KVStore>(tx: &Transaction, ctx: &mut ExecutionContext<S>) -> ExecutionResult {
+    debug!("Executing transaction {}", tx.id);
+
+    ctx.warm_up_keys(&tx.metadata.access_list);
+
+    let loaded;
+    let code: &[MicroOp] = match tx.metadata.to {
+        Some(to) => {
+            // Covered by the up-front balance check.
+            call::move_value(tx.metadata.from, to, tx.metadata.value, ctx);
+            loaded = call::load_code(to, ctx);
+            &loaded
+        }
+        None => &tx.metadata.program,
+    };
+
+    // Moving the value and loading the code are implied by the recipient.
+    let list = &tx.metadata.access_list;
+    ctx.declared = (!list.is_empty()).then(|| list.iter().copied().collect());
+    ctx.undeclared = None;
+    let exit = run_code(code, ctx);
+    ctx.declared = None;
+    match exit {
+        Exit::Stop => ExecutionResult::success(
+            tx.id,
+            ctx.gas_used,
+            ctx.access_sets.clone(),
+            ctx.warm_keys.clone(),
+            ctx.cold_keys.clone(),
+        )
+        .with_refund(calculate_refund(&ctx.schedule, ctx.gas_used, ctx.refund))
+        .with_logs(std::mem::take(&mut ctx.logs)),
+        Exit::Revert(pc) => {
+            debug!("Transaction {} reverted at op {}", tx.id, pc);
+            ctx.rollback();
+            ExecutionResult::reverted(
+                tx.id,
+                ctx.gas_used,
+                ctx.access_sets.clone(),
+                ctx.warm_keys.clone(),
+                ctx.cold_keys.clone(),
+                ExecutionError::Revert(Vec::new()).at(tx.id, pc),
+            )
+        }
+        Exit::Halt(pc, e) => {
+            error!("Transaction {} failed at op {}: {}", tx.id, pc, e);
+            ctx.rollback();
+            // An exceptional halt consumes the whole gas limit, and the
+            // failure itself may depend on what was read.
+            let mut result = ExecutionResult::failure(tx.id, e.at(tx.id, pc));
+            result.gas_used = ctx.gas_limit;
+            result.gross_gas = ctx.gas_limit;
+            result.access_sets.reads = ctx.access_sets.reads.clone();
+            result
+        }
+    }
 }
 
 pub fn execute_serial<S: KVStore>(block: &Block, storage: S) -> SerialExecutionResult<S> {
+    execute_serial_with_schedule(block, storage, &GasSchedule::default())
+}
+
+pub fn execute_serial_with_schedule<S: KVStore>(
+    block: &Block,
+    storage: S,
+    schedule: &GasSchedule,
+) -> SerialExecutionResult<S> {
     info!(
         "Executing block {} serially with {} transactions",
         block.number,
         block.transactions.len()
     );
 
-    let mut ctx = ExecutionContext::new(storage);
+    let mut ctx = ExecutionContext::new(storage)
+        .with_schedule(*schedule)
+        .with_env(BlockEnv::new(block, schedule));
     let mut results = Vec::with_capacity(block.transactions.len());
     let mut total_gas = 0;
+    let mut blob_gas_used = 0;
 
     for tx in &block.transactions {
+        if let Err(e) = check_block_gas(block, schedule, total_gas, blob_gas_used, tx) {
+            debug!("Transaction {} rejected: {}", tx.id, e);
+            results.push(ExecutionResult::failure(tx.id, e));
+            continue;
+        }
+
+        // EIP-2929 access lists are scoped to the transaction.
+        ctx.warm_keys.clear();
         ctx.cold_keys.clear();
         ctx.access_sets = crate::types::AccessSets::new();
         ctx.gas_used = 0;
         ctx.stack.clear();
+        ctx.memory.clear();
+        ctx.journal.clear();
+        ctx.original.clear();
+        ctx.refund = 0;
+        ctx.logs.clear();
 
         let result = execute_transaction(tx, &mut ctx);
+        credit_coinbase(&mut ctx.storage, block.coinbase, &result.priority_fee);
         total_gas += result.gas_used;
+        blob_gas_used += result.blob_gas_used;
         results.push(result);
     }
 
@@ -75,8 +345,11 @@ pub fn execute_serial<S: KVStore>(block: &Block, storage: S) -> SerialExecutionR
 
     SerialExecutionResult {
         storage: final_storage,
+        receipts: build_receipts(&results),
+        failures: count_failures(&results),
         results,
         total_gas,
+        blob_gas_used,
     }
 }
 
@@ -86,6 +359,32 @@ mod tests {
     use crate::storage::MemoryStore;
     use crate::types::{Key, MicroOp, TransactionMetadata, U256};
 
+    /// Runs `tx` as the next transaction of its sender, whatever nonce it
+    /// carries.
+    fn execute_next<S: KVStore>(
+        tx: &Transaction,
+        ctx: &mut ExecutionContext<S>,
+    ) -> ExecutionResult {
+        let mut tx = tx.clone();
+        tx.metadata.nonce = ctx.storage.account(&tx.metadata.from).nonce;
+        execute_transaction(&tx, ctx)
+    }
+
+    /// Numbers the transactions of each sender from nonce zero, in block
+    /// order. Those `left_out` of the block carry the next nonce without
+    /// using it up.
+    fn in_sequence(mut transactions: Vec<Transaction>, left_out: &[u64]) -> Vec<Transaction> {
+        let mut next: BTreeMap<[u8; 20], u64> = BTreeMap::new();
+        for tx in &mut transactions {
+            let nonce = next.entry(tx.metadata.from).or_default();
+            tx.metadata.nonce = *nonce;
+            if !left_out.contains(&tx.id) {
+                *nonce += 1;
+            }
+        }
+        transactions
+    }
+
     #[test]
     fn test_block_execution() {
         let key = Key::new([1u8; 20], [1u8; 32]);
@@ -96,10 +395,7 @@ mod tests {
             gas_hint: 100000,
             metadata: TransactionMetadata {
                 program: vec![MicroOp::SStore(key, U256::from_u64(100))],
-                access_list: vec![],
-                blob_size: 0,
-                nonce: 0,
-                from: [0u8; 20],
+                ..Default::default()
             },
         };
         let block = Block::new(1, vec![tx1]);
@@ -107,4 +403,606 @@ mod tests {
         assert_eq!(result.results.len(), 1);
         assert_eq!(result.storage.get(&key), U256::from_u64(100));
     }
+
+    #[test]
+    fn test_control_flow() {
+        let flag = Key::new([1u8; 20], [1u8; 32]);
+        let counter = Key::new([2u8; 20], [2u8; 32]);
+        let mut ctx = ExecutionContext::new(MemoryStore::new());
+        ctx.storage.set(counter, U256::from_u64(3));
+
+        // while counter != 0 { counter = 0 }
+        let program = vec![
+            MicroOp::JumpDest,
+            MicroOp::SLoad(counter),
+            MicroOp::IsZero,
+            MicroOp::JumpI(6),
+            MicroOp::SStore(counter, U256::ZERO),
+            MicroOp::Jump(0),
+            MicroOp::JumpDest,
+            MicroOp::Stop,
+            MicroOp::SStore(counter, U256::ONE),
+        ];
+        let mut tx = Transaction {
+            id: 1,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 100000,
+            metadata: TransactionMetadata {
+                program,
+                ..Default::default()
+            },
+        };
+        let result = execute_transaction(&tx, &mut ctx);
+        assert!(result.success);
+        assert_eq!(ctx.storage.get(&counter), U256::ZERO);
+
+        // revert unless flag is set
+        tx.metadata.program = vec![
+            MicroOp::SStore(counter, U256::from_u64(9)),
+            MicroOp::SLoad(flag),
+            MicroOp::JumpI(4),
+            MicroOp::Revert,
+            MicroOp::JumpDest,
+        ];
+        ctx.journal.clear();
+        let result = execute_next(&tx, &mut ctx);
+        assert!(result.reverted && result.gas_used > 0);
+        assert!(result.access_sets.reads.contains(&flag));
+        assert_eq!(ctx.storage.get(&counter), U256::ZERO);
+        assert_eq!(result.error, Some(ExecutionError::Revert(vec![]).at(1, 3)));
+
+        tx.metadata.program = vec![MicroOp::Jump(0)];
+        let result = execute_next(&tx, &mut ctx);
+        assert!(!result.success);
+        assert_eq!(result.error, Some(ExecutionError::InvalidJump(0).at(1, 0)));
+    }
+
+    #[test]
+    fn test_failures_by_kind() {
+        let tx = |id: u64, gas_hint: u64, program: Vec<MicroOp>| Transaction {
+            id,
+            reads: vec![],
+            writes: vec![],
+            gas_hint,
+            metadata: TransactionMetadata {
+                program,
+                from: [id as u8; 20],
+                ..Default::default()
+            },
+        };
+        let block = Block::new(
+            1,
+            vec![
+                tx(
+                    0,
+                    50_000,
+                    vec![MicroOp::Push(U256::ONE), MicroOp::Pop, MicroOp::Pop],
+                ),
+                tx(1, 50_000, vec![MicroOp::Add(U256::ONE)]),
+                tx(
+                    2,
+                    21_005,
+                    vec![MicroOp::Push(U256::ONE), MicroOp::Push(U256::ONE)],
+                ),
+                tx(3, 50_000, vec![MicroOp::Revert]),
+                tx(4, 20_000, vec![]),
+                tx(5, 50_000, vec![]),
+            ],
+        );
+        let result = execute_serial(&block, MemoryStore::new());
+        assert_eq!(
+            result.results[0].error,
+            Some(ExecutionError::StackUnderflow("POP".to_string()).at(0, 2))
+        );
+        let failures: Vec<_> = result
+            .failures
+            .iter()
+            .map(|(kind, count)| (kind.as_str(), *count))
+            .collect();
+        assert_eq!(
+            failures,
+            [
+                ("IntrinsicGas", 1),
+                ("OutOfGas", 1),
+                ("Revert", 1),
+                ("StackUnderflow", 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nonce_mismatch() {
+        let sender = [1u8; 20];
+        let tx = |id: u64, nonce: u64| Transaction {
+            id,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 50_000,
+            metadata: TransactionMetadata {
+                nonce,
+                from: sender,
+                ..Default::default()
+            },
+        };
+        // a replay and a gap around the one valid transaction
+        let block = Block::new(1, vec![tx(0, 0), tx(1, 0), tx(2, 2), tx(3, 1)]);
+        let result = execute_serial(&block, MemoryStore::new());
+        let success: Vec<bool> = result.results.iter().map(|r| r.success).collect();
+        assert_eq!(success, [true, false, false, true]);
+        assert_eq!(
+            result.results[1].error,
+            Some(ExecutionError::NonceMismatch {
+                expected: 1,
+                actual: 0
+            })
+        );
+        assert_eq!(result.results[2].gas_used, 0);
+        let nonce = Key::account(sender, AccountField::Nonce);
+        assert!(result.results[2].access_sets.reads.contains(&nonce));
+        assert_eq!(result.failures.get("NonceMismatch"), Some(&2));
+        assert_eq!(result.storage.account(&sender).nonce, 2);
+    }
+
+    #[test]
+    fn test_undeclared_access() {
+        let (declared, other) = (
+            Key::new([4u8; 20], [1u8; 32]),
+            Key::new([4u8; 20], [2u8; 32]),
+        );
+        let contract = [0xcc; 20];
+        let mut storage = MemoryStore::new();
+        deploy_code(&mut storage, contract, vec![MicroOp::SLoad(other)]);
+        let call = vec![
+            MicroOp::Push(U256::ZERO),
+            MicroOp::Push(U256::ZERO),
+            MicroOp::Push(U256::ZERO),
+            MicroOp::Push(U256::from_address(&contract)),
+            MicroOp::Push(U256::from_u64(50_000)),
+            MicroOp::Call,
+        ];
+        let callee =
+            [AccountField::CodeHash, AccountField::Balance].map(|f| Key::account(contract, f));
+        let tx = |id: u64, access_list: Vec<Key>, program: Vec<MicroOp>| Transaction {
+            id,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 100_000,
+            metadata: TransactionMetadata {
+                program,
+                access_list,
+                from: [id as u8 + 1; 20],
+                ..Default::default()
+            },
+        };
+        let block = Block::new(
+            1,
+            vec![
+                tx(
+                    0,
+                    vec![declared],
+                    vec![MicroOp::SStore(declared, U256::ONE)],
+                ),
+                // no list, no commitment
+                tx(1, vec![], vec![MicroOp::SLoad(other)]),
+                tx(
+                    2,
+                    vec![declared],
+                    vec![
+                        MicroOp::SStore(declared, U256::from_u64(2)),
+                        MicroOp::SLoad(other),
+                    ],
+                ),
+                // caught in the callee, yet fails the whole transaction
+                tx(3, [declared].into_iter().chain(callee).collect(), call),
+            ],
+        );
+        let result = execute_serial(&block, storage);
+        let success: Vec<bool> = result.results.iter().map(|r| r.success).collect();
+        assert_eq!(success, [true, true, false, false]);
+        let undeclared = |id, pc| Some(ExecutionError::UndeclaredAccess(other).at(id, pc));
+        assert_eq!(result.results[2].error, undeclared(2, 1));
+        assert_eq!(result.results[3].error, undeclared(3, 5));
+        assert_eq!(result.failures.get("UndeclaredAccess"), Some(&2));
+        assert_eq!(result.results[2].gas_used, 100_000);
+        assert_eq!(result.storage.get(&declared), U256::ONE);
+    }
+
+    #[test]
+    fn test_sstore_refund_cap() {
+        let key = Key::new([4u8; 20], [4u8; 32]);
+        let other = Key::new([5u8; 20], [5u8; 32]);
+        let mut storage = MemoryStore::new();
+        storage.set(key, U256::ONE);
+        storage.set(other, U256::ONE);
+        let mut tx = Transaction {
+            id: 1,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 100000,
+            metadata: TransactionMetadata {
+                program: vec![
+                    MicroOp::SStore(key, U256::ZERO),
+                    MicroOp::SStore(other, U256::ZERO),
+                ],
+                ..Default::default()
+            },
+        };
+
+        let mut ctx = ExecutionContext::new(storage);
+        let result = execute_transaction(&tx, &mut ctx);
+        // two cold resets of 2100 + 2900; the 2 * 4800 clear refund is
+        // capped at a fifth
+        let gas = GasSchedule::default();
+        assert_eq!(
+            result.gross_gas,
+            gas.tx_base + 2 * (gas.cold_sload + gas.sstore_reset)
+        );
+        assert!(2 * gas.sstore_clears_refund > result.gas_refund);
+        assert_eq!(
+            result.gas_refund,
+            result.gross_gas / gas.max_refund_quotient
+        );
+        assert_eq!(result.gas_used, result.gross_gas - result.gas_refund);
+
+        ctx.storage.set(key, U256::ONE);
+        ctx.original.clear();
+        ctx.gas_used = 0;
+        tx.metadata.program = vec![MicroOp::SStore(key, U256::ZERO), MicroOp::Revert];
+        let result = execute_next(&tx, &mut ctx);
+        assert!(result.reverted);
+        assert_eq!(result.gas_refund, 0);
+        assert_eq!(result.gas_used, gas.tx_base + gas.sstore_reset);
+    }
+
+    #[test]
+    fn test_transient_storage() {
+        let lock = Key::new([3u8; 20], [3u8; 32]);
+        let mut tx = Transaction {
+            id: 1,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 100000,
+            metadata: TransactionMetadata {
+                // reentrancy guard: revert if locked, then lock
+                program: vec![
+                    MicroOp::TLoad(lock),
+                    MicroOp::JumpI(4),
+                    MicroOp::TStore(lock, U256::ONE),
+                    MicroOp::Stop,
+                    MicroOp::JumpDest,
+                    MicroOp::Revert,
+                ],
+                ..Default::default()
+            },
+        };
+
+        let mut ctx = ExecutionContext::new(MemoryStore::new());
+        let result = execute_transaction(&tx, &mut ctx);
+        assert!(result.success);
+        let gas = GasSchedule::default();
+        assert_eq!(result.gas_used, gas.tx_base + 2 * gas.warm_sload + gas.high);
+        // storage is only touched by the nonce bump
+        let nonce = Key::account([0u8; 20], AccountField::Nonce);
+        assert_eq!(result.access_sets.writes.len(), 1);
+        assert!(result.access_sets.writes.contains(&nonce));
+        // cleared at tx end, so the next tx is not locked out
+        assert!(ctx.transient.is_empty());
+        ctx.gas_used = 0;
+        assert!(execute_next(&tx, &mut ctx).success);
+
+        tx.metadata.program = vec![
+            MicroOp::TStore(lock, U256::from_u64(5)),
+            MicroOp::TLoad(lock),
+            MicroOp::TStoreTop(lock),
+            MicroOp::Revert,
+        ];
+        let mut ctx = ExecutionContext::new(MemoryStore::new());
+        let result = run_program(&tx, &mut ctx);
+        assert!(result.reverted);
+        assert_eq!(ctx.tload(&lock), U256::ZERO);
+
+        // transient storage only exists from Cancun on
+        let mut ctx =
+            ExecutionContext::new(MemoryStore::new()).with_schedule(GasSchedule::london());
+        let result = execute_transaction(&tx, &mut ctx);
+        assert!(!result.success);
+        assert_eq!(
+            result.error.unwrap().cause(),
+            &ExecutionError::UnsupportedOp("TSTORE".to_string())
+        );
+    }
+
+    #[test]
+    fn test_gas_limits() {
+        let key = Key::new([6u8; 20], [6u8; 32]);
+        let gas = GasSchedule::default();
+        let tx = |id: u64, gas_hint: u64| Transaction {
+            id,
+            reads: vec![],
+            writes: vec![key],
+            gas_hint,
+            metadata: TransactionMetadata {
+                program: vec![MicroOp::SStore(key, U256::from_u64(id))],
+                ..Default::default()
+            },
+        };
+        let exact = gas.tx_base + gas.cold_sload + gas.sstore_set;
+
+        let block = Block::new(
+            1,
+            in_sequence(
+                vec![
+                    tx(1, gas.tx_base - 1),
+                    tx(2, exact - 1),
+                    tx(3, exact),
+                    tx(4, exact),
+                ],
+                &[1, 4],
+            ),
+        )
+        .with_gas_limit(2 * exact);
+        let result = execute_serial(&block, MemoryStore::new());
+        let outcome: Vec<(bool, u64)> = result
+            .results
+            .iter()
+            .map(|r| (r.success, r.gas_used))
+            .collect();
+        // invalid, out of gas (charged in full), fits, exceeds the block
+        assert_eq!(
+            outcome,
+            vec![(false, 0), (false, exact - 1), (true, exact), (false, 0)]
+        );
+        assert_eq!(
+            result.results[3].error,
+            Some(ExecutionError::BlockGasLimit {
+                gas_limit: exact,
+                remaining: 1,
+            })
+        );
+        assert_eq!(result.results[1].error.as_ref().unwrap().kind(), "OutOfGas");
+        assert_eq!(result.total_gas, 2 * exact - 1);
+        assert_eq!(result.storage.get(&key), U256::from_u64(3));
+    }
+
+    #[test]
+    fn test_blob_gas_and_fees() {
+        let gas = GasSchedule::default();
+        let tx = |id: u64, blobs: u64, program: Vec<MicroOp>| Transaction {
+            id,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 50000,
+            metadata: TransactionMetadata {
+                program,
+                blob_size: blobs * BYTES_PER_BLOB,
+                from: [1u8; 20],
+                max_fee_per_gas: U256::ONE,
+                ..Default::default()
+            },
+        };
+        let excess = 10 * gas.blob_base_fee_update_fraction;
+        let transactions = vec![
+            tx(1, 4, vec![]),
+            // fails, but still pays for its blobs
+            tx(2, 2, vec![MicroOp::Pop]),
+            tx(3, 1, vec![]),
+            tx(4, 0, vec![]),
+        ];
+        let block = Block::new(1, in_sequence(transactions.clone(), &[3]))
+            .with_excess_blob_gas(excess)
+            .with_base_fee(U256::ONE);
+        let funded = U256::from_u64(1 << 40);
+        let storage = || {
+            let mut storage = MemoryStore::new();
+            let account = crate::types::Account {
+                balance: funded,
+                ..Default::default()
+            };
+            storage.set_account([1u8; 20], &account);
+            storage
+        };
+
+        let result = execute_serial(&block, storage());
+        let blob_gas: Vec<u64> = result.results.iter().map(|r| r.blob_gas_used).collect();
+        let per_blob = gas.blob_gas_per_blob;
+        assert_eq!(blob_gas, vec![4 * per_blob, 2 * per_blob, 0, 0]);
+        assert!(!result.results[1].success);
+        assert_eq!(
+            result.results[2].error.as_ref().unwrap().kind(),
+            "BlobGasLimit"
+        );
+        assert!(result.results[3].success);
+        assert_eq!(result.blob_gas_used, gas.max_blob_gas_per_block);
+
+        let base_fee = calculate_blob_base_fee(&gas, excess);
+        assert_eq!(base_fee, U256::from_u64(22026));
+        assert_eq!(
+            result.results[1].blob_fee,
+            base_fee.mul(&U256::from_u64(2 * per_blob))
+        );
+        // gas at one wei, and blobs even for the failed transaction
+        let spent = result.results.iter().fold(U256::ZERO, |spent, r| {
+            spent.add(&U256::from_u64(r.gas_used)).add(&r.blob_fee)
+        });
+        assert!(!spent.is_zero());
+        assert_eq!(
+            result.storage.account(&[1u8; 20]).balance,
+            funded.sub(&spent)
+        );
+        assert_eq!(
+            calculate_excess_blob_gas(&gas, excess, result.blob_gas_used),
+            excess + gas.max_blob_gas_per_block - gas.target_blob_gas_per_block
+        );
+
+        let block = Block::new(1, in_sequence(transactions, &[1, 2, 3])).with_base_fee(U256::ONE);
+        let london = execute_serial_with_schedule(&block, storage(), &GasSchedule::london());
+        assert_eq!(london.blob_gas_used, 0);
+        assert_eq!(
+            london.results[0].error,
+            Some(ExecutionError::BlobsUnsupported)
+        );
+        assert!(london.results[3].success);
+    }
+
+    #[test]
+    fn test_contract_call() {
+        let (sender, contract) = ([1u8; 20], [0xcc; 20]);
+        let key = Key::new(contract, [0u8; 32]);
+        let mut storage = MemoryStore::new();
+        deploy_code(
+            &mut storage,
+            contract,
+            vec![
+                MicroOp::Push(U256::ZERO),
+                MicroOp::CallDataLoad,
+                MicroOp::SStoreTop(key),
+            ],
+        );
+        let account = crate::types::Account {
+            balance: U256::from_u64(100),
+            ..Default::default()
+        };
+        storage.set_account(sender, &account);
+
+        let mut calldata = vec![0u8; 32];
+        calldata[31] = 42;
+        let tx = |value: u64| Transaction {
+            id: 0,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 100000,
+            metadata: TransactionMetadata {
+                from: sender,
+                to: Some(contract),
+                value: U256::from_u64(value),
+                calldata: calldata.clone(),
+                ..Default::default()
+            },
+        };
+
+        let mut ctx = ExecutionContext::new(storage);
+        let result = execute_transaction(&tx(30), &mut ctx);
+        assert!(result.success);
+        assert_eq!(ctx.storage.get(&key), U256::from_u64(42));
+        assert_eq!(ctx.storage.account(&contract).balance, U256::from_u64(30));
+        assert!(result
+            .access_sets
+            .reads
+            .contains(&Key::account(contract, AccountField::CodeHash)));
+
+        // the sender cannot afford the value, so nothing runs
+        ctx.gas_used = 0;
+        let result = execute_next(&tx(80), &mut ctx);
+        assert_eq!(result.error.unwrap().kind(), "InsufficientBalance");
+        assert_eq!(ctx.storage.account(&sender).balance, U256::from_u64(70));
+    }
+
+    #[test]
+    fn test_sender_create_uses_tx_nonce() {
+        let sender = [1u8; 20];
+        let tx = |id: u64, program: Vec<MicroOp>| Transaction {
+            id,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 1_000_000,
+            metadata: TransactionMetadata {
+                program,
+                nonce: id,
+                from: sender,
+                ..Default::default()
+            },
+        };
+        let deploy = vec![
+            MicroOp::Push(U256::ZERO),
+            MicroOp::Create(vec![MicroOp::Stop]),
+        ];
+        let block = Block::new(1, vec![tx(0, deploy), tx(1, vec![])]);
+        let result = execute_serial(&block, MemoryStore::new());
+        assert!(result.results.iter().all(|r| r.success));
+        assert_eq!(result.storage.account(&sender).nonce, 2);
+        let created = crate::evm::call::create_address(&sender, 0);
+        assert_eq!(result.storage.account(&created).nonce, 1);
+    }
+
+    #[test]
+    fn test_fee_payment() {
+        let gas = GasSchedule::default();
+        let (sender, coinbase) = ([1u8; 20], [0xcb; 20]);
+        let tx = |id: u64, max_fee: u64, program: Vec<MicroOp>| Transaction {
+            id,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 50000,
+            metadata: TransactionMetadata {
+                program,
+                from: sender,
+                max_fee_per_gas: U256::from_u64(max_fee),
+                max_priority_fee_per_gas: U256::from_u64(2),
+                ..Default::default()
+            },
+        };
+        let block = Block::new(
+            1,
+            in_sequence(
+                vec![
+                    // pays base fee + tip for the gas it used
+                    tx(0, 15, vec![]),
+                    // capped at one wei of tip, and pays even though it reverts
+                    tx(1, 11, vec![MicroOp::Revert]),
+                    // cannot afford the base fee
+                    tx(2, 9, vec![]),
+                    // cannot afford 50000 gas at 12 wei from what is left
+                    tx(3, 15, vec![]),
+                ],
+                &[2, 3],
+            ),
+        )
+        .with_coinbase(coinbase)
+        .with_base_fee(U256::from_u64(10));
+
+        let mut storage = MemoryStore::new();
+        let funded = 12 * 50000 + 11 * gas.tx_base + 1;
+        let account = crate::types::Account {
+            balance: U256::from_u64(funded),
+            ..Default::default()
+        };
+        storage.set_account(sender, &account);
+        let result = execute_serial(&block, storage);
+
+        let tips: Vec<U256> = result.results.iter().map(|r| r.priority_fee).collect();
+        let tx_base = U256::from_u64(gas.tx_base);
+        assert_eq!(
+            tips,
+            vec![
+                tx_base.mul(&U256::from_u64(2)),
+                tx_base,
+                U256::ZERO,
+                U256::ZERO
+            ]
+        );
+        assert!(result.results[1].reverted);
+        let kinds: Vec<_> = result
+            .results
+            .iter()
+            .map(|r| r.error.as_ref().map(ExecutionError::kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                None,
+                Some("Revert"),
+                Some("FeeCapBelowBaseFee"),
+                Some("InsufficientBalance")
+            ]
+        );
+
+        let paid = 12 * gas.tx_base + 11 * gas.tx_base;
+        let sender = result.storage.account(&sender);
+        assert_eq!(sender.balance, U256::from_u64(funded - paid));
+        assert_eq!(sender.nonce, 2);
+        // the base fee is burned
+        let coinbase = result.storage.account(&coinbase);
+        assert_eq!(coinbase.balance, tx_base.mul(&U256::from_u64(3)));
+    }
 }
@@ -1,89 +1,449 @@
-use crate::evm::{context::ExecutionContext, gas::*};
+use crate::evm::call::{access_account, execute_call, execute_create, move_value};
+use crate::evm::context::{ExecutionContext, MEMORY_LIMIT};
+use crate::evm::gas::*;
+#[cfg(not(feature = "fast-hash"))]
+use crate::evm::keccak::keccak256;
 use crate::storage::KVStore;
-use crate::types::{Key, MicroOp, U256};
-use blake3;
+use crate::types::{AccountField, BinaryOp, ExecutionError, Key, Log, MicroOp, U256};
 
-pub fn execute_op<S: KVStore>(op: &MicroOp, ctx: &mut ExecutionContext<S>) -> Result<(), String> {
+/// What the interpreter does after an op.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Next,
+    Jump(usize),
+    Stop,
+    Revert,
+}
+
+pub fn execute_op<S: KVStore>(
+    op: &MicroOp,
+    ctx: &mut ExecutionContext<S>,
+) -> Result<Flow, ExecutionError> {
     match op {
+        MicroOp::Jump(target) => return execute_jump(*target, ctx),
+        MicroOp::JumpI(target) => return execute_jumpi(*target, ctx),
+        // Both halts are free in every schedule.
+        MicroOp::Stop => return Ok(Flow::Stop),
+        MicroOp::Revert => return Ok(Flow::Revert),
+        MicroOp::Transfer(to, amount) => return execute_transfer(*to, *amount, ctx),
+        MicroOp::Call => execute_call(false, ctx),
+        MicroOp::StaticCall => execute_call(true, ctx),
+        MicroOp::Create(code) => execute_create(code, false, ctx),
+        MicroOp::Create2(code) => execute_create(code, true, ctx),
+        MicroOp::CallDataLoad => execute_calldataload(ctx),
+        MicroOp::CallDataSize => {
+            ctx.consume_gas(ctx.schedule.base)?;
+            ctx.push(U256::from_u64(ctx.calldata.len() as u64))
+        }
+        MicroOp::Caller => execute_env(U256::from_address(&ctx.caller), ctx),
+        MicroOp::Balance => execute_balance(ctx),
+        MicroOp::Number => execute_env(U256::from_u64(ctx.env.number), ctx),
+        MicroOp::Timestamp => execute_env(U256::from_u64(ctx.env.timestamp), ctx),
+        MicroOp::Coinbase => execute_env(U256::from_address(&ctx.env.coinbase), ctx),
+        MicroOp::ChainId => execute_env(U256::from_u64(ctx.env.chain_id), ctx),
+        MicroOp::BaseFee => execute_env(ctx.env.base_fee, ctx),
+        MicroOp::Log0 | MicroOp::Log1 | MicroOp::Log2 | MicroOp::Log3 | MicroOp::Log4 => {
+            execute_log(op.log_topics().unwrap(), ctx)
+        }
         MicroOp::SLoad(key) => execute_sload(*key, ctx),
         MicroOp::SStore(key, value) => execute_sstore(*key, *value, ctx),
-        MicroOp::Add(value) => execute_add(*value, ctx),
-        MicroOp::Sub(value) => execute_sub(*value, ctx),
+        MicroOp::SStoreTop(key) => {
+            let value = ctx.pop("SSTORE")?;
+            execute_sstore(*key, value, ctx)
+        }
+        MicroOp::SStoreTopAt(address) => {
+            let slot = ctx.pop("SSTORE")?;
+            let value = ctx.pop("SSTORE")?;
+            execute_sstore(Key::new(*address, slot.to_be_bytes()), value, ctx)
+        }
+        MicroOp::TLoad(key) => {
+            ctx.consume_gas(transient_gas("TLOAD", ctx)?)?;
+            let value = ctx.tload(key);
+            ctx.push(value)
+        }
+        MicroOp::TStore(key, value) => execute_tstore(*key, *value, ctx),
+        MicroOp::TStoreTop(key) => {
+            let value = ctx.pop("TSTORE")?;
+            execute_tstore(*key, value, ctx)
+        }
+        MicroOp::SLoadDyn => {
+            let key = pop_key("SLOAD", ctx)?;
+            execute_sload(key, ctx)
+        }
+        MicroOp::SStoreDyn => {
+            let key = pop_key("SSTORE", ctx)?;
+            let value = ctx.pop("SSTORE")?;
+            execute_sstore(key, value, ctx)
+        }
+        MicroOp::MapSlot => execute_map_slot(ctx),
+        MicroOp::Add(value) => execute_binary(BinaryOp::Add, Some(*value), ctx),
+        MicroOp::Sub(value) => execute_binary(BinaryOp::Sub, Some(*value), ctx),
+        MicroOp::Mul(value) => execute_binary(BinaryOp::Mul, Some(*value), ctx),
+        MicroOp::Div(value) => execute_binary(BinaryOp::Div, Some(*value), ctx),
+        MicroOp::SDiv(value) => execute_binary(BinaryOp::SDiv, Some(*value), ctx),
+        MicroOp::Mod(value) => execute_binary(BinaryOp::Mod, Some(*value), ctx),
+        MicroOp::SMod(value) => execute_binary(BinaryOp::SMod, Some(*value), ctx),
+        MicroOp::Exp(value) => execute_binary(BinaryOp::Exp, Some(*value), ctx),
+        MicroOp::SignExtend(value) => execute_binary(BinaryOp::SignExtend, Some(*value), ctx),
+        MicroOp::Lt(value) => execute_binary(BinaryOp::Lt, Some(*value), ctx),
+        MicroOp::Gt(value) => execute_binary(BinaryOp::Gt, Some(*value), ctx),
+        MicroOp::SLt(value) => execute_binary(BinaryOp::SLt, Some(*value), ctx),
+        MicroOp::SGt(value) => execute_binary(BinaryOp::SGt, Some(*value), ctx),
+        MicroOp::Eq(value) => execute_binary(BinaryOp::Eq, Some(*value), ctx),
+        MicroOp::IsZero => {
+            execute_unary(ctx.schedule.very_low, "ISZERO", ctx, |a| a.is_zero().into())
+        }
+        MicroOp::And(value) => execute_binary(BinaryOp::And, Some(*value), ctx),
+        MicroOp::Or(value) => execute_binary(BinaryOp::Or, Some(*value), ctx),
+        MicroOp::Xor(value) => execute_binary(BinaryOp::Xor, Some(*value), ctx),
+        MicroOp::Not => execute_unary(ctx.schedule.very_low, "NOT", ctx, U256::not),
+        MicroOp::Shl(value) => execute_binary(BinaryOp::Shl, Some(*value), ctx),
+        MicroOp::Shr(value) => execute_binary(BinaryOp::Shr, Some(*value), ctx),
+        MicroOp::Sar(value) => execute_binary(BinaryOp::Sar, Some(*value), ctx),
+        MicroOp::Binary(op) => execute_binary(*op, None, ctx),
+        MicroOp::Push(value) => execute_push(*value, ctx),
+        MicroOp::Pop => execute_pop(ctx),
+        MicroOp::Dup(n) => execute_dup(*n, ctx),
+        MicroOp::Swap(n) => execute_swap(*n, ctx),
         MicroOp::Keccak(data) => execute_keccak(data, ctx),
+        MicroOp::MLoad => execute_mload(ctx),
+        MicroOp::MStore => execute_mstore(ctx),
+        MicroOp::MStore8 => execute_mstore8(ctx),
+        MicroOp::MSize => {
+            ctx.consume_gas(ctx.schedule.base)?;
+            ctx.push(U256::from_u64(ctx.memory.len() as u64))
+        }
+        MicroOp::KeccakMem => execute_keccak_mem(ctx),
+        MicroOp::JumpDest => ctx.consume_gas(ctx.schedule.jumpdest),
         MicroOp::NoOp => execute_noop(ctx),
     }
+    .map(|()| Flow::Next)
 }
 
-fn execute_sload<S: KVStore>(key: Key, ctx: &mut ExecutionContext<S>) -> Result<(), String> {
+fn execute_jump<S: KVStore>(
+    target: usize,
+    ctx: &mut ExecutionContext<S>,
+) -> Result<Flow, ExecutionError> {
+    ctx.consume_gas(ctx.schedule.mid)?;
+    Ok(Flow::Jump(target))
+}
+
+fn execute_jumpi<S: KVStore>(
+    target: usize,
+    ctx: &mut ExecutionContext<S>,
+) -> Result<Flow, ExecutionError> {
+    ctx.consume_gas(ctx.schedule.high)?;
+    if ctx.pop("JUMPI")?.is_zero() {
+        Ok(Flow::Next)
+    } else {
+        Ok(Flow::Jump(target))
+    }
+}
+
+fn check_storage_key(key: &Key) -> Result<(), ExecutionError> {
+    if key.is_account() {
+        return Err(ExecutionError::ReservedSlot(*key));
+    }
+    Ok(())
+}
+
+fn execute_sload<S: KVStore>(
+    key: Key,
+    ctx: &mut ExecutionContext<S>,
+) -> Result<(), ExecutionError> {
+    check_storage_key(&key)?;
     let is_cold = !ctx.is_warm(&key);
-    ctx.consume_gas(calculate_sload_gas(is_cold))?;
+    ctx.consume_gas(calculate_sload_gas(&ctx.schedule, is_cold))?;
 
     if is_cold {
         ctx.cold_keys.insert(key);
         ctx.warm_keys.insert(key);
     }
 
-    ctx.access_sets.add_read(key);
-    ctx.stack.push(ctx.storage.get(&key));
-    Ok(())
+    ctx.record_read(key);
+    let value = ctx.storage.get(&key);
+    ctx.push(value)
 }
 
 fn execute_sstore<S: KVStore>(
     key: Key,
     value: U256,
     ctx: &mut ExecutionContext<S>,
-) -> Result<(), String> {
+) -> Result<(), ExecutionError> {
+    check_storage_key(&key)?;
+    ctx.check_writable("SSTORE")?;
     let is_cold = !ctx.is_warm(&key);
     let current_value = ctx.storage.get(&key);
-    ctx.consume_gas(calculate_sstore_gas(is_cold, current_value, value))?;
+    let original = *ctx.original.entry(key).or_insert(current_value);
+    let (gas, refund) =
+        calculate_sstore_gas(&ctx.schedule, is_cold, &original, &current_value, &value);
+    ctx.consume_gas(gas)?;
+    ctx.refund += refund;
 
     if is_cold {
         ctx.cold_keys.insert(key);
         ctx.warm_keys.insert(key);
     }
 
-    ctx.access_sets.add_write(key);
+    ctx.record_write(key);
+    ctx.journal.push((key, current_value));
     ctx.storage.set(key, value);
     Ok(())
 }
 
-fn execute_add<S: KVStore>(value: U256, ctx: &mut ExecutionContext<S>) -> Result<(), String> {
-    ctx.consume_gas(ADD_COST)?;
-    if let Some(a) = ctx.stack.pop() {
-        ctx.stack.push(a.add(&value));
-        Ok(())
-    } else {
-        Err("Stack underflow in ADD".to_string())
+/// Moves value from the executing account, reverting when its balance
+/// falls short. The recipient account is warm afterwards.
+fn execute_transfer<S: KVStore>(
+    to: [u8; 20],
+    amount: U256,
+    ctx: &mut ExecutionContext<S>,
+) -> Result<Flow, ExecutionError> {
+    if !amount.is_zero() {
+        ctx.check_writable("Transfer")?;
+    }
+    access_account(to, ctx)?;
+    if amount.is_zero() {
+        return Ok(Flow::Next);
     }
+    ctx.consume_gas(ctx.schedule.call_value)?;
+    if !move_value(ctx.address, to, amount, ctx) {
+        return Ok(Flow::Revert);
+    }
+    Ok(Flow::Next)
 }
 
-fn execute_sub<S: KVStore>(value: U256, ctx: &mut ExecutionContext<S>) -> Result<(), String> {
-    ctx.consume_gas(SUB_COST)?;
-    if let Some(a) = ctx.stack.pop() {
-        ctx.stack.push(a.sub(&value));
-        Ok(())
-    } else {
-        Err("Stack underflow in SUB".to_string())
+/// Pops a byte offset and pushes the calldata word starting there; bytes
+/// past the end read as zero.
+fn execute_calldataload<S: KVStore>(ctx: &mut ExecutionContext<S>) -> Result<(), ExecutionError> {
+    ctx.consume_gas(ctx.schedule.very_low)?;
+    let offset = ctx.pop("CALLDATALOAD")?;
+    let mut word = [0u8; 32];
+    if let Some(start) = offset
+        .to_u64()
+        .filter(|&start| start < ctx.calldata.len() as u64)
+    {
+        let data = &ctx.calldata[start as usize..];
+        let len = data.len().min(32);
+        word[..len].copy_from_slice(&data[..len]);
     }
+    ctx.push(U256::from_be_bytes(word))
+}
+
+fn transient_gas<S: KVStore>(name: &str, ctx: &ExecutionContext<S>) -> Result<u64, ExecutionError> {
+    ctx.schedule
+        .transient
+        .ok_or_else(|| ExecutionError::UnsupportedOp(name.to_string()))
 }
 
-fn execute_keccak<S: KVStore>(data: &[u8], ctx: &mut ExecutionContext<S>) -> Result<(), String> {
-    ctx.consume_gas(calculate_keccak_gas(data.len()))?;
-    let hash = blake3::hash(data);
-    let mut bytes = [0u8; 32];
-    bytes.copy_from_slice(&hash.as_bytes()[..32]);
-    ctx.stack.push(U256(bytes));
+fn execute_tstore<S: KVStore>(
+    key: Key,
+    value: U256,
+    ctx: &mut ExecutionContext<S>,
+) -> Result<(), ExecutionError> {
+    ctx.check_writable("TSTORE")?;
+    ctx.consume_gas(transient_gas("TSTORE", ctx)?)?;
+    ctx.tstore(key, value);
     Ok(())
 }
 
-fn execute_noop<S: KVStore>(ctx: &mut ExecutionContext<S>) -> Result<(), String> {
-    ctx.consume_gas(NOOP_COST)
+/// Pops the right-hand operand unless it is an immediate, then pops `a` and
+/// pushes `a op b`.
+fn execute_binary<S: KVStore>(
+    op: BinaryOp,
+    immediate: Option<U256>,
+    ctx: &mut ExecutionContext<S>,
+) -> Result<(), ExecutionError> {
+    let b = match immediate {
+        Some(value) => value,
+        None => ctx.pop(op.name())?,
+    };
+    ctx.consume_gas(calculate_binary_gas(&ctx.schedule, op, &b))?;
+    let a = ctx.pop(op.name())?;
+    ctx.push(op.apply(&a, &b))
+}
+
+fn execute_unary<S: KVStore>(
+    cost: u64,
+    name: &str,
+    ctx: &mut ExecutionContext<S>,
+    f: impl Fn(&U256) -> U256,
+) -> Result<(), ExecutionError> {
+    ctx.consume_gas(cost)?;
+    let a = ctx.pop(name)?;
+    ctx.push(f(&a))
+}
+
+fn execute_push<S: KVStore>(
+    value: U256,
+    ctx: &mut ExecutionContext<S>,
+) -> Result<(), ExecutionError> {
+    ctx.consume_gas(ctx.schedule.very_low)?;
+    ctx.push(value)
+}
+
+fn execute_pop<S: KVStore>(ctx: &mut ExecutionContext<S>) -> Result<(), ExecutionError> {
+    ctx.consume_gas(ctx.schedule.base)?;
+    ctx.pop("POP").map(|_| ())
+}
+
+fn execute_dup<S: KVStore>(n: u8, ctx: &mut ExecutionContext<S>) -> Result<(), ExecutionError> {
+    ctx.consume_gas(ctx.schedule.very_low)?;
+    if !(1..=16).contains(&n) {
+        return Err(ExecutionError::InvalidOperand(format!("DUP{}", n)));
+    }
+    let depth = ctx.stack.len();
+    if depth < n as usize {
+        return Err(ExecutionError::StackUnderflow(format!("DUP{}", n)));
+    }
+    let value = ctx.stack[depth - n as usize];
+    ctx.push(value)
+}
+
+fn execute_swap<S: KVStore>(n: u8, ctx: &mut ExecutionContext<S>) -> Result<(), ExecutionError> {
+    ctx.consume_gas(ctx.schedule.very_low)?;
+    if !(1..=16).contains(&n) {
+        return Err(ExecutionError::InvalidOperand(format!("SWAP{}", n)));
+    }
+    let depth = ctx.stack.len();
+    if depth <= n as usize {
+        return Err(ExecutionError::StackUnderflow(format!("SWAP{}", n)));
+    }
+    ctx.stack.swap(depth - 1, depth - 1 - n as usize);
+    Ok(())
+}
+
+/// Pops the slot, then the address.
+fn pop_key<S: KVStore>(name: &str, ctx: &mut ExecutionContext<S>) -> Result<Key, ExecutionError> {
+    let slot = ctx.pop(name)?;
+    let address = ctx.pop(name)?;
+    Ok(Key::from_words(&address, &slot))
+}
+
+/// Keccak-256 of `data`. The `fast-hash` feature swaps in blake3, which is
+/// faster but derives different slots than on-chain.
+pub(crate) fn hash_word(data: &[u8]) -> U256 {
+    #[cfg(feature = "fast-hash")]
+    let hash = *blake3::hash(data).as_bytes();
+    #[cfg(not(feature = "fast-hash"))]
+    let hash = keccak256(data);
+    U256::from_be_bytes(hash)
+}
+
+/// Solidity's layout for `mapping[key]` declared at `slot`.
+pub(crate) fn mapping_slot(key: &U256, slot: &U256) -> U256 {
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(&key.to_be_bytes());
+    data[32..].copy_from_slice(&slot.to_be_bytes());
+    hash_word(&data)
+}
+
+fn execute_keccak<S: KVStore>(
+    data: &[u8],
+    ctx: &mut ExecutionContext<S>,
+) -> Result<(), ExecutionError> {
+    ctx.consume_gas(calculate_keccak_gas(&ctx.schedule, data.len()))?;
+    ctx.push(hash_word(data))
+}
+
+fn execute_keccak_mem<S: KVStore>(ctx: &mut ExecutionContext<S>) -> Result<(), ExecutionError> {
+    let offset = ctx.pop("KECCAK")?;
+    let size = ctx.pop("KECCAK")?;
+    let size = size
+        .to_u64()
+        .filter(|&size| size <= MEMORY_LIMIT)
+        .ok_or(ExecutionError::MemoryOutOfRange)?;
+    ctx.consume_gas(calculate_keccak_gas(&ctx.schedule, size as usize))?;
+    let start = ctx.expand_memory(&offset, size)?;
+    let hash = hash_word(&ctx.memory[start..start + size as usize]);
+    ctx.push(hash)
+}
+
+/// Pushes a value of the block or call frame, at the cost of the base tier.
+fn execute_env<S: KVStore>(
+    value: U256,
+    ctx: &mut ExecutionContext<S>,
+) -> Result<(), ExecutionError> {
+    ctx.consume_gas(ctx.schedule.base)?;
+    ctx.push(value)
+}
+
+/// Charged as an account access, then read like any other balance, the
+/// coinbase's included.
+fn execute_balance<S: KVStore>(ctx: &mut ExecutionContext<S>) -> Result<(), ExecutionError> {
+    let address = Key::from_words(&ctx.pop("BALANCE")?, &U256::ZERO).address;
+    access_account(address, ctx)?;
+    let balance = ctx.read(Key::account(address, AccountField::Balance));
+    ctx.push(balance)
+}
+
+fn execute_log<S: KVStore>(
+    topics: usize,
+    ctx: &mut ExecutionContext<S>,
+) -> Result<(), ExecutionError> {
+    ctx.check_writable("LOG")?;
+    let offset = ctx.pop("LOG")?;
+    let size = ctx.pop("LOG")?;
+    let size = size
+        .to_u64()
+        .filter(|&size| size <= MEMORY_LIMIT)
+        .ok_or(ExecutionError::MemoryOutOfRange)?;
+    let topics = (0..topics)
+        .map(|_| ctx.pop("LOG"))
+        .collect::<Result<Vec<_>, _>>()?;
+    ctx.consume_gas(calculate_log_gas(&ctx.schedule, topics.len(), size))?;
+    let start = ctx.expand_memory(&offset, size)?;
+    let data = ctx.memory[start..start + size as usize].to_vec();
+    ctx.logs.push(Log {
+        address: ctx.address,
+        topics,
+        data,
+    });
+    Ok(())
+}
+
+fn execute_mload<S: KVStore>(ctx: &mut ExecutionContext<S>) -> Result<(), ExecutionError> {
+    ctx.consume_gas(ctx.schedule.very_low)?;
+    let offset = ctx.pop("MLOAD")?;
+    let start = ctx.expand_memory(&offset, 32)?;
+    let mut word = [0u8; 32];
+    word.copy_from_slice(&ctx.memory[start..start + 32]);
+    ctx.push(U256::from_be_bytes(word))
+}
+
+fn execute_mstore<S: KVStore>(ctx: &mut ExecutionContext<S>) -> Result<(), ExecutionError> {
+    ctx.consume_gas(ctx.schedule.very_low)?;
+    let offset = ctx.pop("MSTORE")?;
+    let value = ctx.pop("MSTORE")?;
+    let start = ctx.expand_memory(&offset, 32)?;
+    ctx.memory[start..start + 32].copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+fn execute_mstore8<S: KVStore>(ctx: &mut ExecutionContext<S>) -> Result<(), ExecutionError> {
+    ctx.consume_gas(ctx.schedule.very_low)?;
+    let offset = ctx.pop("MSTORE8")?;
+    let value = ctx.pop("MSTORE8")?;
+    let start = ctx.expand_memory(&offset, 1)?;
+    ctx.memory[start] = value.to_be_bytes()[31];
+    Ok(())
+}
+
+fn execute_map_slot<S: KVStore>(ctx: &mut ExecutionContext<S>) -> Result<(), ExecutionError> {
+    ctx.consume_gas(calculate_keccak_gas(&ctx.schedule, 64))?;
+    let slot = ctx.pop("MAPSLOT")?;
+    let key = ctx.pop("MAPSLOT")?;
+    ctx.push(mapping_slot(&key, &slot))
+}
+
+fn execute_noop<S: KVStore>(ctx: &mut ExecutionContext<S>) -> Result<(), ExecutionError> {
+    ctx.consume_gas(ctx.schedule.noop)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::storage::MemoryStore;
+    use crate::types::AccountField;
 
     #[test]
     fn test_ops() {
@@ -92,4 +452,263 @@ mod tests {
         execute_sstore(key, U256::from_u64(42), &mut ctx).unwrap();
         assert_eq!(ctx.storage.get(&key), U256::from_u64(42));
     }
+
+    #[test]
+    fn test_arithmetic_ops() {
+        let mut ctx = ExecutionContext::new(MemoryStore::new());
+        let key = Key::new([1u8; 20], [1u8; 32]);
+        execute_sstore(key, U256::from_u64(10), &mut ctx).unwrap();
+
+        let program = [
+            MicroOp::SLoad(key),
+            MicroOp::Mul(U256::from_u64(3)),
+            MicroOp::Div(U256::ZERO),
+            MicroOp::IsZero,
+            MicroOp::Shl(U256::from_u64(4)),
+            MicroOp::Lt(U256::from_u64(17)),
+        ];
+        for op in &program {
+            execute_op(op, &mut ctx).unwrap();
+        }
+        assert_eq!(ctx.stack, vec![U256::ONE]);
+        assert!(execute_op(
+            &MicroOp::Not,
+            &mut ExecutionContext::new(MemoryStore::new())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_sign_extend_forms() {
+        // the immediate is the byte index, as the pushed operand of Binary
+        let mut ctx = ExecutionContext::new(MemoryStore::new());
+        execute_op(&MicroOp::Push(U256::from_u64(0x80)), &mut ctx).unwrap();
+        execute_op(&MicroOp::SignExtend(U256::ZERO), &mut ctx).unwrap();
+        assert_eq!(ctx.stack, vec![U256::MAX.sub(&U256::from_u64(0x7f))]);
+
+        let program = [
+            MicroOp::Push(U256::from_u64(0x80)),
+            MicroOp::Push(U256::ZERO),
+            MicroOp::Binary(BinaryOp::SignExtend),
+        ];
+        let mut stacked = ExecutionContext::new(MemoryStore::new());
+        for op in &program {
+            execute_op(op, &mut stacked).unwrap();
+        }
+        assert_eq!(stacked.stack, ctx.stack);
+
+        // a byte index past the word leaves the value alone
+        ctx.stack = vec![U256::from_u64(0x80)];
+        execute_op(&MicroOp::SignExtend(U256::from_u64(31)), &mut ctx).unwrap();
+        assert_eq!(ctx.stack, vec![U256::from_u64(0x80)]);
+    }
+
+    #[test]
+    fn test_sstore_from_stack() {
+        let mut ctx = ExecutionContext::new(MemoryStore::new());
+        let key = Key::new([1u8; 20], [1u8; 32]);
+        execute_sstore(key, U256::from_u64(5), &mut ctx).unwrap();
+
+        let program = [
+            MicroOp::SLoad(key),
+            MicroOp::Add(U256::from_u64(2)),
+            MicroOp::Dup(1),
+            MicroOp::SStoreTop(key),
+            MicroOp::Push(U256::from_u64(9)),
+            MicroOp::SStoreTopAt([2u8; 20]),
+        ];
+        for op in &program {
+            execute_op(op, &mut ctx).unwrap();
+        }
+        let computed = Key::new([2u8; 20], U256::from_u64(9).to_be_bytes());
+        assert_eq!(ctx.storage.get(&key), U256::from_u64(7));
+        assert_eq!(ctx.storage.get(&computed), U256::from_u64(7));
+        assert!(ctx.access_sets.writes.contains(&computed));
+        assert!(ctx.stack.is_empty());
+    }
+
+    #[test]
+    fn test_dynamic_keys() {
+        let mut ctx = ExecutionContext::new(MemoryStore::new());
+        let address = U256::from_u64(0xaa);
+        let base = U256::from_u64(3);
+        let user = U256::from_u64(42);
+        let program = [
+            MicroOp::Push(U256::from_u64(100)),
+            MicroOp::Push(address),
+            MicroOp::Push(user),
+            MicroOp::Push(base),
+            MicroOp::MapSlot,
+            MicroOp::SStoreDyn,
+            MicroOp::Push(address),
+            MicroOp::Push(user),
+            MicroOp::Push(base),
+            MicroOp::MapSlot,
+            MicroOp::SLoadDyn,
+        ];
+        for op in &program {
+            execute_op(op, &mut ctx).unwrap();
+        }
+
+        let key = Key::from_words(&address, &mapping_slot(&user, &base));
+        assert_eq!(key.address[19], 0xaa);
+        assert_eq!(ctx.stack, vec![U256::from_u64(100)]);
+        assert!(ctx.access_sets.writes.contains(&key));
+        assert!(ctx.access_sets.reads.contains(&key));
+    }
+
+    #[test]
+    #[cfg(not(feature = "fast-hash"))]
+    fn test_solidity_mapping_slot() {
+        // keccak256(abi.encode(0, 0)): `mapping[0]` declared at slot 0
+        let slot = mapping_slot(&U256::ZERO, &U256::ZERO);
+        assert_eq!(
+            slot.to_be_bytes()[..4],
+            [0xad, 0x32, 0x28, 0xb6],
+            "got {:?}",
+            slot
+        );
+        let mut expected = [0u8; 32];
+        expected.copy_from_slice(&keccak256(&[0u8; 64]));
+        assert_eq!(slot, U256::from_be_bytes(expected));
+    }
+
+    #[test]
+    fn test_memory_ops() {
+        let mut ctx = ExecutionContext::new(MemoryStore::new());
+        let (key, slot) = (U256::from_u64(42), U256::from_u64(3));
+        let program = [
+            MicroOp::Push(key),
+            MicroOp::Push(U256::ZERO),
+            MicroOp::MStore,
+            MicroOp::Push(slot),
+            MicroOp::Push(U256::from_u64(32)),
+            MicroOp::MStore,
+            MicroOp::Push(U256::from_u64(64)),
+            MicroOp::Push(U256::ZERO),
+            MicroOp::KeccakMem,
+            MicroOp::Push(U256::from_u64(0xab)),
+            MicroOp::Push(U256::from_u64(95)),
+            MicroOp::MStore8,
+            MicroOp::Push(U256::from_u64(64)),
+            MicroOp::MLoad,
+            MicroOp::MSize,
+        ];
+        for op in &program {
+            execute_op(op, &mut ctx).unwrap();
+        }
+
+        assert_eq!(
+            ctx.stack,
+            vec![
+                mapping_slot(&key, &slot),
+                U256::from_u64(0xab),
+                U256::from_u64(96)
+            ]
+        );
+        let gas = &ctx.schedule;
+        // nine pushes plus three stores and a load, all very-low tier
+        let expected = 13 * gas.very_low
+            + calculate_keccak_gas(gas, 64)
+            + gas.base
+            + calculate_memory_gas(gas, 3);
+        assert_eq!(ctx.gas_used, expected);
+    }
+
+    #[test]
+    fn test_stack_ops() {
+        let mut ctx = ExecutionContext::new(MemoryStore::new());
+        let program = [
+            MicroOp::Push(U256::from_u64(10)),
+            MicroOp::Push(U256::from_u64(3)),
+            MicroOp::Dup(2),
+            MicroOp::Swap(1),
+            MicroOp::Binary(BinaryOp::Sub),
+            MicroOp::Binary(BinaryOp::Mul),
+        ];
+        for op in &program {
+            execute_op(op, &mut ctx).unwrap();
+        }
+        // [10, 3] -> [10, 3, 10] -> [10, 10, 3] -> [10, 7] -> [70]
+        assert_eq!(ctx.stack, vec![U256::from_u64(70)]);
+        assert_eq!(ctx.gas_used, 5 * ctx.schedule.very_low + ctx.schedule.low);
+
+        execute_op(&MicroOp::Pop, &mut ctx).unwrap();
+        assert!(execute_op(&MicroOp::Pop, &mut ctx).is_err());
+        assert!(execute_op(&MicroOp::Dup(17), &mut ctx).is_err());
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mut ctx = ExecutionContext::new(MemoryStore::new());
+        let (from, to) = ([1u8; 20], [2u8; 20]);
+        let from_key = Key::account(from, AccountField::Balance);
+        let to_key = Key::account(to, AccountField::Balance);
+        ctx.address = from;
+        ctx.storage.set(from_key, U256::from_u64(100));
+
+        let transfer = MicroOp::Transfer(to, U256::from_u64(60));
+        assert_eq!(execute_op(&transfer, &mut ctx), Ok(Flow::Next));
+        assert_eq!(ctx.storage.get(&from_key), U256::from_u64(40));
+        assert_eq!(ctx.storage.get(&to_key), U256::from_u64(60));
+        let gas = &ctx.schedule;
+        assert_eq!(ctx.gas_used, gas.cold_account_access + gas.call_value);
+        assert!(ctx.access_sets.reads.contains(&from_key));
+        assert!(ctx.access_sets.writes.contains(&to_key));
+
+        // the second one falls short and leaves balances alone
+        assert_eq!(execute_op(&transfer, &mut ctx), Ok(Flow::Revert));
+        assert_eq!(ctx.storage.get(&from_key), U256::from_u64(40));
+        assert_eq!(ctx.storage.get(&to_key), U256::from_u64(60));
+
+        // account state is out of reach of plain storage ops
+        assert!(execute_op(&MicroOp::SLoad(from_key), &mut ctx).is_err());
+        assert!(execute_op(&MicroOp::SStore(to_key, U256::ZERO), &mut ctx).is_err());
+    }
+
+    #[test]
+    fn test_log() {
+        let mut ctx = ExecutionContext::new(MemoryStore::new());
+        ctx.address = [3u8; 20];
+        let program = [
+            MicroOp::Push(U256::from_u64(0xabcd)),
+            MicroOp::Push(U256::ZERO),
+            MicroOp::MStore,
+            MicroOp::Push(U256::from_u64(9)),
+            MicroOp::Push(U256::from_u64(2)),
+            MicroOp::Push(U256::from_u64(30)),
+        ];
+        for op in &program {
+            execute_op(op, &mut ctx).unwrap();
+        }
+        let gas_used = ctx.gas_used;
+        assert_eq!(execute_op(&MicroOp::Log1, &mut ctx), Ok(Flow::Next));
+        assert_eq!(
+            ctx.gas_used - gas_used,
+            calculate_log_gas(&ctx.schedule, 1, 2)
+        );
+        assert_eq!(
+            ctx.logs,
+            vec![Log {
+                address: [3u8; 20],
+                topics: vec![U256::from_u64(9)],
+                data: vec![0xab, 0xcd],
+            }]
+        );
+        assert!(ctx.stack.is_empty());
+
+        // logs are dropped with the frame that emitted them
+        let checkpoint = ctx.checkpoint();
+        ctx.push(U256::ZERO).unwrap();
+        ctx.push(U256::ZERO).unwrap();
+        execute_op(&MicroOp::Log0, &mut ctx).unwrap();
+        assert_eq!(ctx.logs.len(), 2);
+        ctx.revert_to(checkpoint);
+        assert_eq!(ctx.logs.len(), 1);
+
+        ctx.is_static = true;
+        ctx.push(U256::ZERO).unwrap();
+        ctx.push(U256::ZERO).unwrap();
+        assert!(execute_op(&MicroOp::Log0, &mut ctx).is_err());
+    }
 }
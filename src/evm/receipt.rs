@@ -0,0 +1,140 @@
+use crate::evm::ops::hash_word;
+use crate::types::{ExecutionResult, Log};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// 2048-bit filter over the addresses and topics of a set of logs.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Bloom(pub [u8; 256]);
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self([0u8; 256])
+    }
+}
+
+impl Bloom {
+    pub fn from_logs(logs: &[Log]) -> Self {
+        let mut bloom = Self::default();
+        for log in logs {
+            bloom.accrue(&log.address);
+            for topic in &log.topics {
+                bloom.accrue(&topic.to_be_bytes());
+            }
+        }
+        bloom
+    }
+
+    /// Sets the three bits picked by the first three byte pairs of the hash
+    /// of `input`, as the Yellow Paper's M3:2048.
+    pub fn accrue(&mut self, input: &[u8]) {
+        let hash = hash_word(input).to_be_bytes();
+        for pair in hash[..6].chunks(2) {
+            let bit = (u16::from_be_bytes([pair[0], pair[1]]) & 2047) as usize;
+            self.0[255 - bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    pub fn contains(&self, input: &[u8]) -> bool {
+        let mut probe = Self::default();
+        probe.accrue(input);
+        probe.0.iter().zip(&self.0).all(|(p, b)| p & b == *p)
+    }
+}
+
+impl std::fmt::Debug for Bloom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex: String = self.0.iter().map(|b| format!("{:02x}", b)).collect();
+        write!(f, "Bloom(0x{})", hex)
+    }
+}
+
+impl Serialize for Bloom {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = self.0.iter().map(|b| format!("{:02x}", b)).collect();
+        serializer.serialize_str(&format!("0x{}", hex))
+    }
+}
+
+impl<'de> Deserialize<'de> for Bloom {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let digits = text.strip_prefix("0x").unwrap_or(&text);
+        if digits.len() != 512 {
+            return Err(serde::de::Error::custom("bloom must be 256 bytes"));
+        }
+        let mut bloom = Bloom::default();
+        for (byte, pair) in bloom.0.iter_mut().zip(digits.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(serde::de::Error::custom)?;
+            *byte = u8::from_str_radix(pair, 16).map_err(serde::de::Error::custom)?;
+        }
+        Ok(bloom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub tx_id: u64,
+    pub status: bool,
+    /// Gas used by this and every earlier transaction of the block.
+    pub cumulative_gas_used: u64,
+    pub logs: Vec<Log>,
+    pub logs_bloom: Bloom,
+}
+
+/// Receipts for `results`, which must be in block order whatever order
+/// they were executed in. Transactions left out of the block get a failed
+/// receipt that adds no gas.
+pub fn build_receipts(results: &[ExecutionResult]) -> Vec<Receipt> {
+    let mut cumulative_gas_used = 0;
+    results
+        .iter()
+        .map(|result| {
+            cumulative_gas_used += result.gas_used;
+            Receipt {
+                tx_id: result.tx_id,
+                status: result.success,
+                cumulative_gas_used,
+                logs: result.logs.clone(),
+                logs_bloom: Bloom::from_logs(&result.logs),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::U256;
+
+    #[test]
+    fn test_bloom() {
+        let log = Log {
+            address: [0x11; 20],
+            topics: vec![U256::from_u64(7)],
+            data: vec![1, 2, 3],
+        };
+        let bloom = Bloom::from_logs(std::slice::from_ref(&log));
+        assert!(bloom.contains(&log.address));
+        assert!(bloom.contains(&U256::from_u64(7).to_be_bytes()));
+        assert!(!bloom.contains(&[0x22; 20]));
+        // at most three bits per item
+        let bits: u32 = bloom.0.iter().map(|b| b.count_ones()).sum();
+        assert!((1..=6).contains(&bits));
+
+        let json = serde_json::to_string(&bloom).unwrap();
+        assert_eq!(serde_json::from_str::<Bloom>(&json).unwrap(), bloom);
+    }
+
+    #[test]
+    #[cfg(not(feature = "fast-hash"))]
+    fn test_bloom_bits() {
+        // keccak256 of an all-zero address starts 5380c7b7ae81
+        let mut bloom = Bloom::default();
+        bloom.accrue(&[0u8; 20]);
+        let mut expected = Bloom::default();
+        for bit in [0x5380 & 2047, 0xc7b7 & 2047, 0xae81 & 2047] {
+            expected.0[255 - bit / 8] |= 1 << (bit % 8);
+        }
+        assert_eq!(bloom, expected);
+    }
+}
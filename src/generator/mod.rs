@@ -1,13 +1,24 @@
+use crate::storage::{KVStore, MemoryStore};
 use crate::types::*;
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
 
+/// One ether, far more than any generated transaction spends.
+const SENDER_BALANCE: u64 = 1_000_000_000_000_000_000;
+
 pub struct BlockGenerator {
     pub n_tx: usize,
     pub key_space: usize,
     pub conflict_ratio: f64,
     pub cold_ratio: f64,
     pub seed: u64,
+    /// Fraction of transactions whose write target depends on a stored flag.
+    pub branch_ratio: f64,
+    /// Fraction of writes that store `load(key) + delta` instead of a constant.
+    pub rmw_ratio: f64,
+    /// Fraction of transactions that update `mapping[load(pointer)]`, a key
+    /// only known at runtime.
+    pub dynamic_ratio: f64,
 }
 
 impl BlockGenerator {
@@ -24,9 +35,92 @@ impl BlockGenerator {
             conflict_ratio,
             cold_ratio,
             seed,
+            branch_ratio: 0.0,
+            rmw_ratio: 0.0,
+            dynamic_ratio: 0.0,
         }
     }
 
+    pub fn with_branch_ratio(mut self, branch_ratio: f64) -> Self {
+        self.branch_ratio = branch_ratio;
+        self
+    }
+
+    pub fn with_rmw_ratio(mut self, rmw_ratio: f64) -> Self {
+        self.rmw_ratio = rmw_ratio;
+        self
+    }
+
+    pub fn with_dynamic_ratio(mut self, dynamic_ratio: f64) -> Self {
+        self.dynamic_ratio = dynamic_ratio;
+        self
+    }
+
+    /// Upper bound on the gas of a generated program, assuming every slot
+    /// is cold and every store sets a fresh slot. Generated programs only
+    /// jump forward, so each op runs at most once.
+    fn gas_limit_for(program: &[MicroOp]) -> u64 {
+        let gas = crate::evm::GasSchedule::default();
+        program.iter().fold(gas.tx_base, |total, op| {
+            total
+                + match op {
+                    MicroOp::SStore(..)
+                    | MicroOp::SStoreTop(_)
+                    | MicroOp::SStoreTopAt(_)
+                    | MicroOp::SStoreDyn => gas.cold_sload + gas.sstore_set,
+                    MicroOp::SLoad(_) | MicroOp::SLoadDyn => gas.cold_sload,
+                    // keccak and memory ops over a couple of words
+                    _ => gas.warm_sload,
+                }
+        })
+    }
+
+    /// `store(a, s, load(a, s) + delta)` with `s = mapping_slot(load(pointer), base)`.
+    fn generate_mapping_update(
+        &self,
+        contract: [u8; 20],
+        pointer: Key,
+        program: &mut Vec<MicroOp>,
+        rng: &mut StdRng,
+    ) {
+        program.push(MicroOp::Push(U256::from_address(&contract)));
+        program.push(MicroOp::SLoad(pointer));
+        program.push(MicroOp::Push(U256::from_u64(rng.gen_range(0..4))));
+        program.push(MicroOp::MapSlot);
+        program.push(MicroOp::Dup(2));
+        program.push(MicroOp::Dup(2));
+        program.push(MicroOp::SLoadDyn);
+        program.push(MicroOp::Add(U256::from_u64(rng.gen_range(1..1000))));
+        program.push(MicroOp::Swap(2));
+        program.push(MicroOp::Swap(1));
+        program.push(MicroOp::SStoreDyn);
+    }
+
+    /// `if load(cond) != 0 { store(taken) } else { store(fallthrough) }`
+    fn generate_branch(
+        &self,
+        cond: Key,
+        taken: Key,
+        fallthrough: Key,
+        program: &mut Vec<MicroOp>,
+        rng: &mut StdRng,
+    ) {
+        let base = program.len();
+        program.push(MicroOp::SLoad(cond));
+        program.push(MicroOp::JumpI(base + 4));
+        program.push(MicroOp::SStore(
+            fallthrough,
+            U256::from_u64(rng.gen_range(1..1000)),
+        ));
+        program.push(MicroOp::Jump(base + 6));
+        program.push(MicroOp::JumpDest);
+        program.push(MicroOp::SStore(
+            taken,
+            U256::from_u64(rng.gen_range(1..1000)),
+        ));
+        program.push(MicroOp::JumpDest);
+    }
+
     fn generate_program(&self, reads: &[Key], writes: &[Key], rng: &mut StdRng) -> Vec<MicroOp> {
         let mut program = Vec::new();
 
@@ -38,11 +132,22 @@ impl BlockGenerator {
         }
         for key in writes {
             let value = U256::from_u64(rng.gen_range(1..1000));
-            program.push(MicroOp::SStore(*key, value));
+            if self.rmw_ratio > 0.0 && rng.gen::<f64>() < self.rmw_ratio {
+                program.push(MicroOp::SLoad(*key));
+                program.push(MicroOp::Add(value));
+                program.push(MicroOp::SStoreTop(*key));
+            } else {
+                program.push(MicroOp::SStore(*key, value));
+            }
         }
         if rng.gen::<f64>() < 0.2 {
-            let data: Vec<u8> = (0..32).map(|_| rng.gen()).collect();
-            program.push(MicroOp::Keccak(data));
+            let data: [u8; 32] = rng.gen();
+            program.push(MicroOp::Push(U256::from_be_bytes(data)));
+            program.push(MicroOp::Push(U256::ZERO));
+            program.push(MicroOp::MStore);
+            program.push(MicroOp::Push(U256::from_u64(32)));
+            program.push(MicroOp::Push(U256::ZERO));
+            program.push(MicroOp::KeccakMem);
         }
         for _ in 0..rng.gen_range(0..3) {
             program.push(MicroOp::NoOp);
@@ -83,6 +188,8 @@ impl BlockGenerator {
             self.seed
         );
 
+        let gas = crate::evm::GasSchedule::default();
+        let mut blob_gas_used = 0;
         for tx_id in 0..self.n_tx {
             // Determine read/write set sizes
             let read_count = rng.gen_range(1..=5);
@@ -116,24 +223,52 @@ impl BlockGenerator {
             }
 
             // Generate program from reads/writes
-            let program = self.generate_program(&reads, &writes, &mut rng);
+            let mut program = self.generate_program(&reads, &writes, &mut rng);
+
+            if self.branch_ratio > 0.0
+                && !key_pool.is_empty()
+                && rng.gen::<f64>() < self.branch_ratio
+            {
+                let mut pick = || key_pool[rng.gen_range(0..key_pool.len())];
+                let (cond, taken, fallthrough) = (pick(), pick(), pick());
+                self.generate_branch(cond, taken, fallthrough, &mut program, &mut rng);
+                reads.push(cond);
+                writes.push(taken);
+                writes.push(fallthrough);
+            }
+
+            if self.dynamic_ratio > 0.0
+                && !key_pool.is_empty()
+                && rng.gen::<f64>() < self.dynamic_ratio
+            {
+                let contract = key_pool[rng.gen_range(0..key_pool.len())].address;
+                let pointer = key_pool[rng.gen_range(0..key_pool.len())];
+                self.generate_mapping_update(contract, pointer, &mut program, &mut rng);
+                reads.push(pointer);
+            }
+
+            // Blobs only while the block has blob gas left for them
+            let mut blob_size = 0;
+            if rng.gen::<f64>() < 0.1 {
+                let size = rng.gen_range(1000..100000);
+                let blob_gas = crate::evm::calculate_blob_gas(&gas, size);
+                if blob_gas_used + blob_gas <= gas.max_blob_gas_per_block {
+                    blob_gas_used += blob_gas;
+                    blob_size = size;
+                }
+            }
 
             // Create transaction
             let tx = Transaction {
                 id: tx_id as u64,
                 reads,
                 writes,
-                gas_hint: 100000,
+                gas_hint: Self::gas_limit_for(&program),
                 metadata: TransactionMetadata {
                     program,
-                    access_list: vec![],
-                    blob_size: if rng.gen::<f64>() < 0.1 {
-                        rng.gen_range(1000..100000)
-                    } else {
-                        0
-                    },
-                    nonce: tx_id as u64,
+                    blob_size,
                     from: rng.gen::<[u8; 20]>(),
+                    ..Default::default()
                 },
             };
 
@@ -148,16 +283,30 @@ impl BlockGenerator {
         block
     }
 
+    /// Storage in which every sender of `block` can pay for its gas and
+    /// blobs. Blobs are never free, so generated blocks need it to run.
+    pub fn funded_storage(block: &Block) -> MemoryStore {
+        let mut storage = MemoryStore::new();
+        let account = Account {
+            balance: U256::from_u64(SENDER_BALANCE),
+            ..Account::default()
+        };
+        for tx in &block.transactions {
+            storage.set_account(tx.metadata.from, &account);
+        }
+        storage
+    }
+
     pub fn small() -> Self {
-        Self::new(100, 1000, 0.1, 0.3, 42)
+        Self::new(100, 1000, 0.1, 0.3, 42).with_rmw_ratio(0.5)
     }
 
     pub fn medium() -> Self {
-        Self::new(1000, 10000, 0.2, 0.3, 42)
+        Self::new(1000, 10000, 0.2, 0.3, 42).with_rmw_ratio(0.5)
     }
 
     pub fn large() -> Self {
-        Self::new(5000, 50000, 0.3, 0.4, 42)
+        Self::new(5000, 50000, 0.3, 0.4, 42).with_rmw_ratio(0.5)
     }
 
     pub fn no_conflicts(n_tx: usize, seed: u64) -> Self {
@@ -185,5 +334,32 @@ mod tests {
         assert_eq!(block.transactions.len(), 100);
         let block2 = BlockGenerator::new(50, 500, 0.2, 0.3, 42).generate();
         assert_eq!(block2.transactions.len(), 50);
+
+        let branchy = BlockGenerator::new(50, 500, 0.2, 0.3, 42)
+            .with_branch_ratio(1.0)
+            .generate();
+        assert!(branchy.transactions.iter().all(|tx| tx
+            .metadata
+            .program
+            .iter()
+            .any(|op| matches!(op, MicroOp::JumpI(_)))));
+
+        let dynamic = BlockGenerator::new(20, 50, 0.5, 0.3, 42)
+            .with_dynamic_ratio(1.0)
+            .generate();
+        let mut ctx = crate::evm::ExecutionContext::new(BlockGenerator::funded_storage(&dynamic));
+        for tx in &dynamic.transactions {
+            ctx.gas_used = 0;
+            assert!(crate::evm::execute_transaction(tx, &mut ctx).success);
+        }
+
+        // gas hints cover even the heaviest mix of generated programs
+        let heavy = BlockGenerator::new(100, 20, 0.5, 0.3, 1)
+            .with_branch_ratio(1.0)
+            .with_rmw_ratio(1.0)
+            .with_dynamic_ratio(1.0)
+            .generate();
+        let result = crate::evm::execute_serial(&heavy, BlockGenerator::funded_storage(&heavy));
+        assert!(result.results.iter().all(|r| r.success));
     }
 }
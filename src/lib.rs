@@ -1,3 +1,4 @@
+pub mod analysis;
 pub mod cli;
 pub mod evm;
 pub mod generator;
@@ -8,15 +9,19 @@ pub mod types;
 
 // Re-export commonly used types
 pub use types::{
-    AccessSets, Block, ExecutionResult, Key, Metrics, MicroOp, Transaction, TransactionMetadata,
-    U256,
+    AccessSets, Account, AccountField, BinaryOp, Block, ExecutionError, ExecutionResult, Key, Log,
+    Metrics, MicroOp, Transaction, TransactionMetadata, U256,
 };
 
-pub use evm::{execute_serial, execute_transaction, ExecutionContext, SerialExecutionResult};
+pub use analysis::{BlockAnalysis, BlockAnalyzer};
+pub use evm::{
+    deploy_code, execute_serial, execute_serial_with_schedule, execute_transaction,
+    ExecutionContext, GasSchedule, Receipt, SerialExecutionResult,
+};
 pub use generator::BlockGenerator;
 pub use metrics::MetricsCollector;
 pub use scheduler::{
-    AccessListBuilder, AccessOracle, ConflictGraph, HeuristicOracle, MIScheduler,
-    ParallelExecutionResult, ParallelExecutor,
+    AccessListBuilder, AccessOracle, BlockBuilder, ChainExecutor, ConflictGraph, HeuristicOracle,
+    MIScheduler, ParallelExecutionResult, ParallelExecutor,
 };
-pub use storage::{KVStore, MemoryStore};
+pub use storage::{KVStore, MemoryStore, OverlayStore};
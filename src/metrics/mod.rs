@@ -1,7 +1,7 @@
 use crate::evm::SerialExecutionResult;
 use crate::scheduler::{AccessListBuilder, ParallelExecutionResult};
 use crate::storage::KVStore;
-use crate::types::{Block, Metrics};
+use crate::types::{Block, ExecutionResult, Metrics, U256};
 use tracing::info;
 
 pub struct MetricsCollector;
@@ -56,6 +56,12 @@ impl MetricsCollector {
             0.0
         };
 
+        let sum_fees = |fee: fn(&ExecutionResult) -> &U256| {
+            results
+                .iter()
+                .fold(U256::ZERO, |total, r| total.add(fee(r)))
+        };
+
         Metrics {
             waves: waves.len(),
             avg_wave_size,
@@ -69,6 +75,10 @@ impl MetricsCollector {
             tx_latency_p95,
             tx_latency_p99,
             iops,
+            blob_gas_used: parallel_result.blob_gas_used,
+            blob_fees: sum_fees(|r| &r.blob_fee),
+            priority_fees: sum_fees(|r| &r.priority_fee),
+            failures: parallel_result.failures.clone(),
         }
     }
 
@@ -199,6 +209,14 @@ impl MetricsCollector {
         info!("  Preexec Precision: {:.3}", metrics.preexec_precision);
         info!("  Preexec Recall: {:.3}", metrics.preexec_recall);
         info!("  IOPS: {:.2}", metrics.iops);
+        info!(
+            "  Blob Gas: {} (fees {})",
+            metrics.blob_gas_used, metrics.blob_fees
+        );
+        info!("  Priority Fees: {}", metrics.priority_fees);
+        for (kind, count) in &metrics.failures {
+            info!("  Failed ({}): {}", kind, count);
+        }
         info!(
             "  Latency P50/P95/P99: {:.2}/{:.2}/{:.2} ms",
             metrics.tx_latency_p50, metrics.tx_latency_p95, metrics.tx_latency_p99
@@ -211,3 +229,65 @@ impl Default for MetricsCollector {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::execute_serial;
+    use crate::scheduler::{ExactOracle, MIScheduler, ParallelExecutor};
+    use crate::storage::MemoryStore;
+    use crate::types::{Account, Transaction, TransactionMetadata};
+
+    #[test]
+    fn test_fees_beyond_u128() {
+        // 2^120 wei per gas of tip: a single payment overflows a u128
+        let tip = U256::from_u64(1 << 60).mul(&U256::from_u64(1 << 60));
+        let transactions = (0..2u64)
+            .map(|id| Transaction {
+                id,
+                reads: vec![],
+                writes: vec![],
+                gas_hint: 50_000,
+                metadata: TransactionMetadata {
+                    from: [id as u8 + 1; 20],
+                    max_fee_per_gas: tip,
+                    max_priority_fee_per_gas: tip,
+                    ..Default::default()
+                },
+            })
+            .collect();
+        let block = Block::new(1, transactions);
+        let funded = || {
+            let mut store = MemoryStore::new();
+            for tx in &block.transactions {
+                let account = Account {
+                    balance: U256::MAX,
+                    ..Account::default()
+                };
+                store.set_account(tx.metadata.from, &account);
+            }
+            store
+        };
+
+        let serial = execute_serial(&block, funded());
+        let mut executor = ParallelExecutor::new(
+            MIScheduler::new(10000),
+            AccessListBuilder::new(Box::new(ExactOracle)),
+            funded(),
+        );
+        let parallel = executor.execute_parallel(&block);
+        let metrics = MetricsCollector::new().collect(
+            &block,
+            &serial,
+            1.0,
+            &parallel,
+            1.0,
+            executor.access_builder(),
+        );
+
+        let gas_used = parallel.results.iter().map(|r| r.gas_used).sum::<u64>();
+        assert_eq!(metrics.priority_fees, tip.mul(&U256::from_u64(gas_used)));
+        assert_eq!(metrics.priority_fees.to_u128(), None);
+        assert!(metrics.blob_fees.is_zero());
+    }
+}
@@ -1,12 +1,47 @@
-use crate::types::{AccessSets, ExecutionResult, MicroOp, Transaction};
+use crate::evm::call::{code_hash, create2_address};
+use crate::evm::ops::{hash_word, mapping_slot};
+use crate::types::{AccessSets, AccountField, ExecutionResult, Key, MicroOp, Transaction, U256};
 use ahash::AHashMap;
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
+use std::sync::Arc;
 
 pub trait AccessOracle: Send + Sync {
     fn estimate_access_sets(&self, tx: &Transaction) -> AccessSets;
 }
 
+/// Every transaction bumps its sender nonce, and one that pays for gas or
+/// blobs buys them from the sender balance. Fees owed to the coinbase are left out,
+/// since executors credit them without a conflict. The code a transaction
+/// calls is not known ahead of execution, so its accesses are unknown.
+fn add_sender(sets: &mut AccessSets, tx: &Transaction) {
+    let mut keys = vec![Key::account(tx.metadata.from, AccountField::Nonce)];
+    if !tx.metadata.max_fee_per_gas.is_zero()
+        || !tx.metadata.value.is_zero()
+        || tx.metadata.blob_size > 0
+    {
+        keys.push(Key::account(tx.metadata.from, AccountField::Balance));
+    }
+    if let Some(to) = tx.metadata.to {
+        keys.push(Key::account(to, AccountField::Balance));
+        sets.add_read(Key::account(to, AccountField::CodeHash));
+        sets.unknown = true;
+    }
+    for key in keys {
+        sets.add_read(key);
+        sets.add_write(key);
+    }
+}
+
+/// A transfer moves value between the sender and `to` balances.
+fn add_transfer(sets: &mut AccessSets, tx: &Transaction, to: [u8; 20]) {
+    for address in [tx.metadata.from, to] {
+        let balance = Key::account(address, AccountField::Balance);
+        sets.add_read(balance);
+        sets.add_write(balance);
+    }
+}
+
 pub struct HeuristicOracle {
     miss_rate: f64,
     rng: std::sync::Mutex<StdRng>,
@@ -35,6 +70,7 @@ impl AccessOracle for HeuristicOracle {
     fn estimate_access_sets(&self, tx: &Transaction) -> AccessSets {
         let mut sets = AccessSets::new();
         let mut rng = self.rng.lock().unwrap();
+        add_sender(&mut sets, tx);
 
         // tx.reads.iter().for_each(|k|sets.add_read(*k));
         // tx.writes.iter().for_each(|k|sets.add_write(*k));
@@ -48,7 +84,16 @@ impl AccessOracle for HeuristicOracle {
             if rng.gen::<f64>() >= self.miss_rate {
                 match op {
                     MicroOp::SLoad(key) => sets.add_read(*key),
-                    MicroOp::SStore(key, _) => sets.add_write(*key),
+                    MicroOp::SStore(key, _) | MicroOp::SStoreTop(key) => sets.add_write(*key),
+                    MicroOp::SStoreTopAt(_)
+                    | MicroOp::SLoadDyn
+                    | MicroOp::SStoreDyn
+                    | MicroOp::Balance
+                    | MicroOp::Call
+                    | MicroOp::StaticCall
+                    | MicroOp::Create(_)
+                    | MicroOp::Create2(_) => sets.unknown = true,
+                    MicroOp::Transfer(to, _) => add_transfer(&mut sets, tx, *to),
                     _ => {}
                 }
             }
@@ -57,8 +102,311 @@ impl AccessOracle for HeuristicOracle {
     }
 }
 
+/// Derives access sets from the program itself, without sampling misses.
+/// Keys on every branch are included, so conditional programs are
+/// over-approximated. Computed keys are resolved when their operands are
+/// constants; otherwise the sets are marked unknown.
+pub struct ExactOracle;
+
+impl AccessOracle for ExactOracle {
+    fn estimate_access_sets(&self, tx: &Transaction) -> AccessSets {
+        let mut sets = AccessSets::new();
+        let mut stack = ConstStack::default();
+        let mut memory = ConstMemory::default();
+        add_sender(&mut sets, tx);
+        for op in &tx.metadata.program {
+            match op {
+                MicroOp::SLoad(key) => {
+                    sets.add_read(*key);
+                    stack.push(None);
+                }
+                MicroOp::SStore(key, _) => sets.add_write(*key),
+                MicroOp::SStoreTop(key) => {
+                    stack.pop();
+                    sets.add_write(*key);
+                }
+                MicroOp::SStoreTopAt(address) => {
+                    let slot = stack.pop();
+                    stack.pop();
+                    match slot {
+                        Some(slot) => sets.add_write(Key::new(*address, slot.to_be_bytes())),
+                        None => sets.unknown = true,
+                    }
+                }
+                MicroOp::Transfer(to, _) => add_transfer(&mut sets, tx, *to),
+                // The callee is only known once its code is loaded.
+                MicroOp::Call | MicroOp::StaticCall => {
+                    let args = if matches!(op, MicroOp::Call) { 5 } else { 4 };
+                    for _ in 0..args {
+                        stack.pop();
+                    }
+                    stack.push(None);
+                    sets.unknown = true;
+                }
+                // The address depends on the creator nonce at runtime.
+                MicroOp::Create(_) => {
+                    stack.pop();
+                    stack.push(None);
+                    sets.unknown = true;
+                }
+                MicroOp::Create2(code) => {
+                    let value = stack.pop();
+                    let salt = stack.pop();
+                    let creator = tx.metadata.from;
+                    if value != Some(U256::ZERO) {
+                        let balance = Key::account(creator, AccountField::Balance);
+                        sets.add_read(balance);
+                        sets.add_write(balance);
+                    }
+                    match salt.filter(|_| tx.metadata.to.is_none()) {
+                        Some(salt) => {
+                            let address = create2_address(&creator, &salt, &code_hash(code));
+                            for field in [
+                                AccountField::Balance,
+                                AccountField::Nonce,
+                                AccountField::CodeHash,
+                            ] {
+                                sets.add_read(Key::account(address, field));
+                                sets.add_write(Key::account(address, field));
+                            }
+                            stack.push(Some(U256::from_address(&address)));
+                        }
+                        None => {
+                            stack.push(None);
+                            sets.unknown = true;
+                        }
+                    }
+                }
+                MicroOp::CallDataLoad => {
+                    stack.pop();
+                    stack.push(None);
+                }
+                MicroOp::CallDataSize => stack.push(None),
+                MicroOp::Caller => stack.push(Some(U256::from_address(&tx.metadata.from))),
+                MicroOp::Balance => {
+                    match stack.pop() {
+                        Some(address) => sets.add_read(Key::account(
+                            Key::from_words(&address, &U256::ZERO).address,
+                            AccountField::Balance,
+                        )),
+                        None => sets.unknown = true,
+                    }
+                    stack.push(None);
+                }
+                // Block values are not state.
+                MicroOp::Number
+                | MicroOp::Timestamp
+                | MicroOp::Coinbase
+                | MicroOp::ChainId
+                | MicroOp::BaseFee => stack.push(None),
+                // Logs touch no storage.
+                MicroOp::Log0 | MicroOp::Log1 | MicroOp::Log2 | MicroOp::Log3 | MicroOp::Log4 => {
+                    let topics = op.log_topics().unwrap();
+                    for _ in 0..2 + topics {
+                        stack.pop();
+                    }
+                }
+                MicroOp::TLoad(_) => stack.push(None),
+                MicroOp::TStore(_, _) => {}
+                MicroOp::TStoreTop(_) => {
+                    stack.pop();
+                }
+                MicroOp::SLoadDyn => {
+                    match stack.pop_key() {
+                        Some(key) => sets.add_read(key),
+                        None => sets.unknown = true,
+                    }
+                    stack.push(None);
+                }
+                MicroOp::SStoreDyn => {
+                    match stack.pop_key() {
+                        Some(key) => sets.add_write(key),
+                        None => sets.unknown = true,
+                    }
+                    stack.pop();
+                }
+                MicroOp::MapSlot => {
+                    let slot = stack.pop();
+                    let key = stack.pop();
+                    stack.push(key.zip(slot).map(|(key, slot)| mapping_slot(&key, &slot)));
+                }
+                MicroOp::IsZero => {
+                    let a = stack.pop();
+                    stack.push(a.map(|a| a.is_zero().into()));
+                }
+                MicroOp::Not => {
+                    let a = stack.pop();
+                    stack.push(a.map(|a| a.not()));
+                }
+                MicroOp::Push(value) => stack.push(Some(*value)),
+                MicroOp::Pop | MicroOp::JumpI(_) => {
+                    stack.pop();
+                }
+                MicroOp::Dup(n) => stack.dup(*n as usize),
+                MicroOp::Swap(n) => stack.swap(*n as usize),
+                MicroOp::Keccak(data) => stack.push(Some(hash_word(data))),
+                MicroOp::MLoad => {
+                    let offset = stack.pop();
+                    stack.push(memory.load(offset));
+                }
+                MicroOp::MStore => {
+                    let offset = stack.pop();
+                    let value = stack.pop();
+                    memory.store(offset, value);
+                }
+                MicroOp::MStore8 => {
+                    let offset = stack.pop();
+                    stack.pop();
+                    memory.store(offset, None);
+                }
+                MicroOp::MSize => stack.push(None),
+                MicroOp::KeccakMem => {
+                    let offset = stack.pop();
+                    let size = stack.pop();
+                    stack.push(memory.hash(offset, size));
+                }
+                // Reachable from several paths with different stacks.
+                MicroOp::JumpDest => {
+                    stack.values.clear();
+                    memory.forget();
+                }
+                MicroOp::Jump(_) | MicroOp::Stop | MicroOp::Revert | MicroOp::NoOp => {}
+                MicroOp::Add(_)
+                | MicroOp::Sub(_)
+                | MicroOp::Mul(_)
+                | MicroOp::Div(_)
+                | MicroOp::SDiv(_)
+                | MicroOp::Mod(_)
+                | MicroOp::SMod(_)
+                | MicroOp::Exp(_)
+                | MicroOp::SignExtend(_)
+                | MicroOp::Lt(_)
+                | MicroOp::Gt(_)
+                | MicroOp::SLt(_)
+                | MicroOp::SGt(_)
+                | MicroOp::Eq(_)
+                | MicroOp::And(_)
+                | MicroOp::Or(_)
+                | MicroOp::Xor(_)
+                | MicroOp::Shl(_)
+                | MicroOp::Shr(_)
+                | MicroOp::Sar(_)
+                | MicroOp::Binary(_) => {
+                    let (binary, immediate) = op.as_binary().unwrap();
+                    let b = immediate.or_else(|| stack.pop());
+                    let a = stack.pop();
+                    stack.push(a.zip(b).map(|(a, b)| binary.apply(&a, &b)));
+                }
+            }
+        }
+        sets
+    }
+}
+
+/// Memory words with constant values, keyed by word-aligned offset. Words
+/// never written are zero until a write to an unknown offset.
+#[derive(Default)]
+struct ConstMemory {
+    words: AHashMap<u64, Option<U256>>,
+    lost: bool,
+}
+
+impl ConstMemory {
+    fn forget(&mut self) {
+        self.words.clear();
+        self.lost = true;
+    }
+
+    fn load(&self, offset: Option<U256>) -> Option<U256> {
+        let offset = offset?.to_u64().filter(|offset| offset % 32 == 0)?;
+        match self.words.get(&offset) {
+            Some(value) => *value,
+            None if self.lost => None,
+            None => Some(U256::ZERO),
+        }
+    }
+
+    /// A `None` value marks the touched words unknown.
+    fn store(&mut self, offset: Option<U256>, value: Option<U256>) {
+        match offset.and_then(|offset| offset.to_u64()) {
+            Some(offset) if offset % 32 == 0 => {
+                self.words.insert(offset, value);
+            }
+            Some(offset) => {
+                let word = offset - offset % 32;
+                match word.checked_add(32) {
+                    Some(next) => {
+                        self.words.insert(word, None);
+                        self.words.insert(next, None);
+                    }
+                    // Out of range for the interpreter too.
+                    None => self.forget(),
+                }
+            }
+            None => self.forget(),
+        }
+    }
+
+    fn hash(&self, offset: Option<U256>, size: Option<U256>) -> Option<U256> {
+        let offset = offset?.to_u64()?;
+        let size = size?
+            .to_u64()
+            .filter(|size| size % 32 == 0 && *size <= 1024)?;
+        let end = offset.checked_add(size)?;
+        let mut data = Vec::with_capacity(size as usize);
+        for word in (offset..end).step_by(32) {
+            data.extend_from_slice(&self.load(Some(U256::from_u64(word)))?.to_be_bytes());
+        }
+        Some(hash_word(&data))
+    }
+}
+
+/// Top of the stack with constant values tracked; `None` is a value only
+/// known at runtime. Items below the tracked part are unknown as well.
+#[derive(Default)]
+struct ConstStack {
+    values: Vec<Option<U256>>,
+}
+
+impl ConstStack {
+    fn push(&mut self, value: Option<U256>) {
+        self.values.push(value);
+    }
+
+    fn pop(&mut self) -> Option<U256> {
+        self.values.pop().flatten()
+    }
+
+    /// Pops the slot, then the address, as `SLoadDyn` / `SStoreDyn` do.
+    fn pop_key(&mut self) -> Option<Key> {
+        let slot = self.pop();
+        let address = self.pop();
+        address
+            .zip(slot)
+            .map(|(address, slot)| Key::from_words(&address, &slot))
+    }
+
+    fn dup(&mut self, n: usize) {
+        let value = self
+            .values
+            .len()
+            .checked_sub(n)
+            .and_then(|idx| self.values.get(idx).copied().flatten());
+        self.push(value);
+    }
+
+    fn swap(&mut self, n: usize) {
+        let depth = self.values.len();
+        if depth > n {
+            self.values.swap(depth - 1, depth - 1 - n);
+        } else if let Some(top) = self.values.last_mut() {
+            *top = None;
+        }
+    }
+}
+
 pub struct AccessListBuilder {
-    oracle: Box<dyn AccessOracle>,
+    oracle: Arc<dyn AccessOracle>,
     estimated: AHashMap<u64, AccessSets>,
     exact: AHashMap<u64, AccessSets>,
 }
@@ -66,7 +414,7 @@ pub struct AccessListBuilder {
 impl AccessListBuilder {
     pub fn new(oracle: Box<dyn AccessOracle>) -> Self {
         Self {
-            oracle,
+            oracle: Arc::from(oracle),
             estimated: AHashMap::new(),
             exact: AHashMap::new(),
         }
@@ -81,6 +429,14 @@ impl AccessListBuilder {
         self.estimated.insert(tx.id, sets);
     }
 
+    pub fn insert_estimated(&mut self, tx_id: u64, sets: AccessSets) {
+        self.estimated.insert(tx_id, sets);
+    }
+
+    pub fn oracle(&self) -> Arc<dyn AccessOracle> {
+        Arc::clone(&self.oracle)
+    }
+
     pub fn record_after_execution(&mut self, result: &ExecutionResult) {
         self.exact.insert(result.tx_id, result.access_sets.clone());
     }
@@ -89,3 +445,138 @@ impl AccessListBuilder {
         self.estimated.get(&tx_id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TransactionMetadata;
+
+    fn tx(program: Vec<MicroOp>) -> Transaction {
+        Transaction {
+            id: 0,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 100000,
+            metadata: TransactionMetadata {
+                program,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_exact_oracle_dynamic_keys() {
+        let address = U256::from_u64(0xaa);
+        let (user, base) = (U256::from_u64(7), U256::from_u64(1));
+        let constant = tx(vec![
+            MicroOp::Push(address),
+            MicroOp::Push(user),
+            MicroOp::Push(base),
+            MicroOp::MapSlot,
+            MicroOp::SLoadDyn,
+        ]);
+        let sets = ExactOracle.estimate_access_sets(&constant);
+        let key = Key::from_words(&address, &mapping_slot(&user, &base));
+        assert!(!sets.unknown);
+        assert!(sets.reads.contains(&key));
+
+        let pointer = Key::new([1u8; 20], [1u8; 32]);
+        let loaded = tx(vec![
+            MicroOp::Push(address),
+            MicroOp::SLoad(pointer),
+            MicroOp::Push(base),
+            MicroOp::MapSlot,
+            MicroOp::SLoadDyn,
+        ]);
+        let sets = ExactOracle.estimate_access_sets(&loaded);
+        assert!(sets.unknown);
+        assert!(sets.reads.contains(&pointer));
+        assert!(sets.has_conflict_with(&AccessSets::new()));
+
+        let via_memory = tx(vec![
+            MicroOp::Push(address),
+            MicroOp::Push(user),
+            MicroOp::Push(U256::ZERO),
+            MicroOp::MStore,
+            MicroOp::Push(base),
+            MicroOp::Push(U256::from_u64(32)),
+            MicroOp::MStore,
+            MicroOp::Push(U256::from_u64(64)),
+            MicroOp::Push(U256::ZERO),
+            MicroOp::KeccakMem,
+            MicroOp::SLoadDyn,
+        ]);
+        let sets = ExactOracle.estimate_access_sets(&via_memory);
+        assert!(!sets.unknown);
+        assert!(sets.reads.contains(&key));
+    }
+
+    #[test]
+    fn test_exact_oracle_memory_past_range() {
+        // the interpreter halts on both; the oracle must not overflow
+        let far = U256::from_u64(u64::MAX);
+        let store = tx(vec![
+            MicroOp::Push(U256::ONE),
+            MicroOp::Push(far),
+            MicroOp::MStore,
+            MicroOp::Push(U256::ZERO),
+            MicroOp::MLoad,
+        ]);
+        let sets = ExactOracle.estimate_access_sets(&store);
+        assert!(!sets.unknown);
+
+        let hash = tx(vec![
+            MicroOp::Push(U256::from_u64(0xaa)),
+            MicroOp::Push(U256::from_u64(64)),
+            MicroOp::Push(U256::from_u64(u64::MAX - 31)),
+            MicroOp::KeccakMem,
+            MicroOp::SLoadDyn,
+        ]);
+        assert!(ExactOracle.estimate_access_sets(&hash).unknown);
+    }
+
+    #[test]
+    fn test_oracles_track_accounts() {
+        let to = [9u8; 20];
+        let transfer = tx(vec![MicroOp::Transfer(to, U256::ONE)]);
+        let from = transfer.metadata.from;
+        for sets in [
+            ExactOracle.estimate_access_sets(&transfer),
+            HeuristicOracle::with_miss_rate(0.0).estimate_access_sets(&transfer),
+        ] {
+            assert!(sets
+                .writes
+                .contains(&Key::account(from, AccountField::Nonce)));
+            assert!(sets
+                .writes
+                .contains(&Key::account(from, AccountField::Balance)));
+            assert!(sets
+                .reads
+                .contains(&Key::account(to, AccountField::Balance)));
+        }
+
+        // salted deployments land at an address known ahead of time
+        let code = vec![MicroOp::Stop];
+        let salt = U256::from_u64(3);
+        let deploy = tx(vec![
+            MicroOp::Push(salt),
+            MicroOp::Push(U256::ZERO),
+            MicroOp::Create2(code.clone()),
+        ]);
+        let sets = ExactOracle.estimate_access_sets(&deploy);
+        let address = create2_address(&from, &salt, &code_hash(&code));
+        assert!(!sets.unknown);
+        assert!(sets
+            .writes
+            .contains(&Key::account(address, AccountField::CodeHash)));
+
+        // the sender is known, so is a read of its balance
+        let sets = ExactOracle.estimate_access_sets(&tx(vec![MicroOp::Caller, MicroOp::Balance]));
+        assert!(!sets.unknown);
+        assert!(sets
+            .reads
+            .contains(&Key::account(from, AccountField::Balance)));
+        let sets = ExactOracle.estimate_access_sets(&tx(vec![MicroOp::Coinbase, MicroOp::Balance]));
+        assert!(sets.unknown);
+    }
+}
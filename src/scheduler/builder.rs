@@ -0,0 +1,243 @@
+use super::{AccessOracle, ConflictGraph};
+use crate::storage::KVStore;
+use crate::types::{AccessSets, Block, Transaction};
+use ahash::AHashMap;
+use std::collections::VecDeque;
+use tracing::info;
+
+pub struct BuiltBlock {
+    pub block: Block,
+    /// Expected wave plan over the ids of `block.transactions`.
+    pub waves: Vec<Vec<u64>>,
+    pub gas_used: u64,
+    pub excluded: usize,
+}
+
+impl BuiltBlock {
+    pub fn predicted_parallelism(&self) -> f64 {
+        if self.waves.is_empty() {
+            0.0
+        } else {
+            self.block.transactions.len() as f64 / self.waves.len() as f64
+        }
+    }
+}
+
+/// Selects and orders mempool transactions so that the predicted conflict
+/// graph packs into as few waves as possible.
+///
+/// Transactions of one sender are included in strictly increasing nonce order,
+/// starting at the account nonce in `state`, and never share a wave. Once a
+/// sender's transaction is dropped (gas limit or nonce gap) none of its later
+/// transactions are included.
+pub struct BlockBuilder {
+    oracle: Box<dyn AccessOracle>,
+    gas_limit: u64,
+    max_wave_size: usize,
+}
+
+impl BlockBuilder {
+    pub fn new(oracle: Box<dyn AccessOracle>, gas_limit: u64) -> Self {
+        Self {
+            oracle,
+            gas_limit,
+            max_wave_size: usize::MAX,
+        }
+    }
+
+    pub fn with_max_wave_size(mut self, max_wave_size: usize) -> Self {
+        self.max_wave_size = max_wave_size.max(1);
+        self
+    }
+
+    pub fn build<S: KVStore>(&self, number: u64, pool: &[Transaction], state: &S) -> BuiltBlock {
+        // Pool index doubles as the graph node id.
+        let estimates: Vec<(u64, AccessSets)> = pool
+            .iter()
+            .enumerate()
+            .map(|(idx, tx)| (idx as u64, self.oracle.estimate_access_sets(tx)))
+            .collect();
+        let graph = ConflictGraph::build(&estimates);
+
+        let mut queues: AHashMap<[u8; 20], VecDeque<usize>> = AHashMap::new();
+        for (idx, tx) in pool.iter().enumerate() {
+            queues.entry(tx.metadata.from).or_default().push_back(idx);
+        }
+        let mut senders: Vec<[u8; 20]> = Vec::with_capacity(queues.len());
+        for (sender, queue) in queues.iter_mut() {
+            let first = state.account(sender).nonce;
+            let mut sorted: Vec<usize> = queue
+                .drain(..)
+                .filter(|&idx| pool[idx].metadata.nonce >= first)
+                .collect();
+            sorted.sort_by_key(|&idx| (pool[idx].metadata.nonce, idx));
+            sorted.dedup_by_key(|idx| pool[*idx].metadata.nonce);
+            let consecutive = sorted
+                .iter()
+                .enumerate()
+                .take_while(|(offset, &idx)| pool[idx].metadata.nonce == first + *offset as u64)
+                .count();
+            sorted.truncate(consecutive);
+            if !sorted.is_empty() {
+                queue.extend(sorted);
+                senders.push(*sender);
+            }
+        }
+        senders.sort_by_key(|sender| queues[sender][0]);
+
+        let mut gas_used = 0u64;
+        let mut ordered: Vec<usize> = Vec::new();
+        let mut waves: Vec<Vec<u64>> = Vec::new();
+
+        loop {
+            let mut heads: Vec<usize> = senders
+                .iter()
+                .filter_map(|sender| queues[sender].front().copied())
+                .collect();
+            if heads.is_empty() {
+                break;
+            }
+            heads.sort_by_key(|&idx| (graph.degree(idx as u64), idx));
+
+            let mut wave: Vec<usize> = Vec::new();
+            for idx in heads {
+                if wave.len() >= self.max_wave_size {
+                    break;
+                }
+                let sender = pool[idx].metadata.from;
+                let gas = pool[idx].gas_hint;
+                if gas_used.saturating_add(gas) > self.gas_limit {
+                    queues.get_mut(&sender).unwrap().clear();
+                    continue;
+                }
+                if wave
+                    .iter()
+                    .any(|&other| graph.has_conflict(other as u64, idx as u64))
+                {
+                    continue;
+                }
+                gas_used += gas;
+                queues.get_mut(&sender).unwrap().pop_front();
+                wave.push(idx);
+            }
+
+            if wave.is_empty() {
+                continue;
+            }
+            wave.sort_unstable();
+            let start = ordered.len() as u64;
+            waves.push((start..start + wave.len() as u64).collect());
+            ordered.extend(wave);
+        }
+
+        let transactions: Vec<Transaction> = ordered
+            .iter()
+            .enumerate()
+            .map(|(position, &idx)| {
+                let mut tx = pool[idx].clone();
+                tx.id = position as u64;
+                tx
+            })
+            .collect();
+        let excluded = pool.len() - transactions.len();
+
+        info!(
+            "Built block {} with {} of {} pool txs in {} expected waves, {} gas",
+            number,
+            transactions.len(),
+            pool.len(),
+            waves.len(),
+            gas_used
+        );
+
+        BuiltBlock {
+            block: Block::new(number, transactions).with_gas_limit(self.gas_limit),
+            waves,
+            gas_used,
+            excluded,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::BlockGenerator;
+    use crate::scheduler::HeuristicOracle;
+    use crate::storage::MemoryStore;
+    use crate::types::Account;
+
+    #[test]
+    fn test_builder_respects_nonces_and_gas() {
+        let mut pool = BlockGenerator::new(60, 30, 0.5, 0.3, 7)
+            .generate()
+            .transactions;
+        let sender = [9u8; 20];
+        for (nonce, tx) in pool.iter_mut().take(5).rev().enumerate() {
+            tx.metadata.from = sender;
+            tx.metadata.nonce = nonce as u64;
+        }
+
+        let builder = BlockBuilder::new(Box::new(HeuristicOracle::with_miss_rate(0.0)), 2_700_000);
+        let built = builder.build(1, &pool, &MemoryStore::new());
+
+        assert_eq!(built.block.transactions.len(), 43);
+        assert_eq!(built.excluded, 17);
+        assert_eq!(built.block.gas_limit, 2_700_000);
+        let nonces: Vec<u64> = built
+            .block
+            .transactions
+            .iter()
+            .filter(|tx| tx.metadata.from == sender)
+            .map(|tx| tx.metadata.nonce)
+            .collect();
+        assert!(nonces.windows(2).all(|w| w[0] + 1 == w[1]));
+        let planned: usize = built.waves.iter().map(|w| w.len()).sum();
+        assert_eq!(planned, built.block.transactions.len());
+    }
+
+    #[test]
+    fn test_builder_starts_at_account_nonce() {
+        let mut pool = BlockGenerator::new(8, 30, 0.5, 0.3, 7)
+            .generate()
+            .transactions;
+        let (resumed, ahead) = ([9u8; 20], [8u8; 20]);
+        // a stale nonce 2 and then 3..=5 for `resumed`, 4..=5 for `ahead`
+        for (tx, nonce) in pool.iter_mut().zip([5, 2, 4, 3]) {
+            tx.metadata.from = resumed;
+            tx.metadata.nonce = nonce;
+        }
+        for (tx, nonce) in pool.iter_mut().skip(4).zip([4, 5]) {
+            tx.metadata.from = ahead;
+            tx.metadata.nonce = nonce;
+        }
+        let mut state = MemoryStore::new();
+        for sender in [resumed, ahead] {
+            let account = Account {
+                nonce: 3,
+                balance: crate::types::U256::from_u64(u64::MAX),
+                ..Account::default()
+            };
+            state.set_account(sender, &account);
+        }
+
+        let builder = BlockBuilder::new(Box::new(HeuristicOracle::with_miss_rate(0.0)), u64::MAX);
+        let built = builder.build(1, &pool, &state);
+
+        let nonces = |sender| -> Vec<u64> {
+            built
+                .block
+                .transactions
+                .iter()
+                .filter(|tx| tx.metadata.from == sender)
+                .map(|tx| tx.metadata.nonce)
+                .collect()
+        };
+        assert_eq!(nonces(resumed), [3, 4, 5]);
+        assert!(nonces(ahead).is_empty());
+        assert_eq!(built.excluded, 3);
+        assert_eq!(built.block.transactions.len(), 5);
+        let executed = crate::evm::execute_serial(&built.block, state);
+        assert!(!executed.failures.contains_key("NonceMismatch"));
+    }
+}
@@ -0,0 +1,223 @@
+use super::{AccessListBuilder, MIScheduler, ParallelExecutionResult, ParallelExecutor};
+use crate::storage::MemoryStore;
+use crate::types::{AccessSets, Block};
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::sync_channel;
+use std::thread;
+use std::time::Instant;
+use tracing::info;
+
+struct PlannedBlock {
+    block: Block,
+    estimates: Vec<(u64, AccessSets)>,
+    waves: Vec<Vec<u64>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StageStats {
+    pub busy_ms: f64,
+    pub utilization: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PipelineStats {
+    pub blocks: usize,
+    pub wall_time_ms: f64,
+    pub estimation: StageStats,
+    pub scheduling: StageStats,
+    pub execution: StageStats,
+}
+
+pub struct ChainBlockResult {
+    pub number: u64,
+    pub hash: [u8; 32],
+    pub parent_hash: [u8; 32],
+    pub result: ParallelExecutionResult,
+}
+
+pub struct ChainExecutionResult {
+    pub storage: MemoryStore,
+    pub blocks: Vec<ChainBlockResult>,
+    pub stats: PipelineStats,
+}
+
+/// Executes consecutive blocks with access estimation and scheduling of block
+/// N+1 overlapping execution of block N. Execution and commit stay on a single
+/// stage, so blocks are applied to storage strictly in chain order.
+pub struct ChainExecutor {
+    scheduler: MIScheduler,
+    executor: ParallelExecutor,
+    depth: usize,
+}
+
+impl ChainExecutor {
+    pub fn new(
+        scheduler: MIScheduler,
+        access_builder: AccessListBuilder,
+        storage: MemoryStore,
+    ) -> Self {
+        Self {
+            executor: ParallelExecutor::new(scheduler.clone(), access_builder, storage),
+            scheduler,
+            depth: 2,
+        }
+    }
+
+    /// Number of planned blocks allowed to wait for the execution stage.
+    pub fn with_depth(mut self, depth: usize) -> Self {
+        self.depth = depth.max(1);
+        self
+    }
+
+    pub fn executor(&self) -> &ParallelExecutor {
+        &self.executor
+    }
+
+    pub fn execute_chain(&mut self, blocks: Vec<Block>) -> ChainExecutionResult {
+        info!("Executing chain of {} blocks", blocks.len());
+
+        let oracle = self.executor.access_builder().oracle();
+        let scheduler = &self.scheduler;
+        let executor = &mut self.executor;
+        let (sender, receiver) = sync_channel::<PlannedBlock>(self.depth);
+
+        let start = Instant::now();
+        let (estimation_ms, scheduling_ms, execution_ms, chain) = thread::scope(|s| {
+            let planner = s.spawn(move || {
+                let mut estimation_ms = 0.0;
+                let mut scheduling_ms = 0.0;
+                let mut parent_hash = None;
+
+                for mut block in blocks {
+                    if let Some(hash) = parent_hash {
+                        block.parent_hash = hash;
+                    }
+                    parent_hash = Some(block.hash());
+
+                    let stage = Instant::now();
+                    let estimates: Vec<(u64, AccessSets)> = block
+                        .transactions
+                        .iter()
+                        .map(|tx| (tx.id, oracle.estimate_access_sets(tx)))
+                        .collect();
+                    estimation_ms += stage.elapsed().as_secs_f64() * 1000.0;
+
+                    let stage = Instant::now();
+                    let waves = scheduler.schedule_with_sets(&block, &estimates);
+                    scheduling_ms += stage.elapsed().as_secs_f64() * 1000.0;
+
+                    let planned = PlannedBlock {
+                        block,
+                        estimates,
+                        waves,
+                    };
+                    if sender.send(planned).is_err() {
+                        break;
+                    }
+                }
+                (estimation_ms, scheduling_ms)
+            });
+
+            let mut execution_ms = 0.0;
+            let mut chain = Vec::new();
+            for planned in receiver {
+                let stage = Instant::now();
+                for (tx_id, sets) in planned.estimates {
+                    executor.access_builder_mut().insert_estimated(tx_id, sets);
+                }
+                let result = executor.execute_waves(&planned.block, planned.waves);
+                execution_ms += stage.elapsed().as_secs_f64() * 1000.0;
+
+                chain.push(ChainBlockResult {
+                    number: planned.block.number,
+                    hash: planned.block.hash(),
+                    parent_hash: planned.block.parent_hash,
+                    result,
+                });
+            }
+
+            let (estimation_ms, scheduling_ms) = planner.join().expect("planner stage panicked");
+            (estimation_ms, scheduling_ms, execution_ms, chain)
+        });
+        let wall_time_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+        let stage = |busy_ms: f64| StageStats {
+            busy_ms,
+            utilization: if wall_time_ms > 0.0 {
+                busy_ms / wall_time_ms
+            } else {
+                0.0
+            },
+        };
+        let stats = PipelineStats {
+            blocks: chain.len(),
+            wall_time_ms,
+            estimation: stage(estimation_ms),
+            scheduling: stage(scheduling_ms),
+            execution: stage(execution_ms),
+        };
+
+        info!(
+            "Chain execution complete: {} blocks in {:.2} ms (estimation {:.1}%, scheduling {:.1}%, execution {:.1}%)",
+            stats.blocks,
+            stats.wall_time_ms,
+            stats.estimation.utilization * 100.0,
+            stats.scheduling.utilization * 100.0,
+            stats.execution.utilization * 100.0
+        );
+
+        ChainExecutionResult {
+            storage: self.executor.storage().clone(),
+            blocks: chain,
+            stats,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::execute_serial;
+    use crate::generator::BlockGenerator;
+    use crate::storage::KVStore;
+    use crate::types::{Key, MicroOp, U256};
+
+    #[test]
+    fn test_chain_matches_serial() {
+        let shared = Key::new([7u8; 20], [7u8; 32]);
+        let blocks: Vec<Block> = (0..3)
+            .map(|i| {
+                let mut block = BlockGenerator::no_conflicts(50, i).generate();
+                block.number = i + 1;
+                // Every block overwrites the same slot, so the final value
+                // depends on blocks being committed in chain order.
+                let last = block.transactions.last_mut().unwrap();
+                last.gas_hint += 25_000;
+                last.metadata
+                    .program
+                    .push(MicroOp::SStore(shared, U256::from_u64(i + 1)));
+                block
+            })
+            .collect();
+
+        let mut serial = MemoryStore::new();
+        for block in &blocks {
+            serial = execute_serial(block, serial).storage;
+        }
+
+        let mut chain = ChainExecutor::new(
+            MIScheduler::new(10000),
+            AccessListBuilder::with_heuristic(),
+            MemoryStore::new(),
+        );
+        let result = chain.execute_chain(blocks);
+
+        assert_eq!(result.blocks.len(), 3);
+        assert_eq!(result.blocks[1].parent_hash, result.blocks[0].hash);
+        assert_eq!(result.blocks[2].parent_hash, result.blocks[1].hash);
+        assert_eq!(result.storage.get(&shared), U256::from_u64(3));
+        for key in serial.keys() {
+            assert_eq!(result.storage.get(&key), serial.get(&key));
+        }
+    }
+}
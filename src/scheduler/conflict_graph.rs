@@ -1,5 +1,10 @@
-use crate::types::AccessSets;
+use crate::types::{AccessSets, Key};
 use ahash::{AHashMap, AHashSet};
+use std::fmt::Write as _;
+
+const WAVE_COLORS: [&str; 8] = [
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7",
+];
 
 #[derive(Clone)]
 pub struct ConflictGraph {
@@ -32,8 +37,201 @@ impl ConflictGraph {
             .is_some_and(|neighbors| neighbors.contains(&tx2))
     }
 
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges.values().map(|n| n.len()).sum::<usize>() / 2
+    }
+
+    /// Node ids in ascending order.
+    pub fn node_ids(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.nodes.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Edges as `(low, high)` pairs in ascending order.
+    pub fn edges(&self) -> Vec<(u64, u64)> {
+        let mut edges: Vec<(u64, u64)> = self
+            .edges
+            .iter()
+            .flat_map(|(&a, neighbors)| {
+                neighbors
+                    .iter()
+                    .filter(move |&&b| a < b)
+                    .map(move |&b| (a, b))
+            })
+            .collect();
+        edges.sort_unstable();
+        edges
+    }
+
+    pub fn access_sets(&self, tx: u64) -> Option<&AccessSets> {
+        self.nodes.get(&tx)
+    }
+
+    pub fn degree(&self, tx: u64) -> usize {
+        self.edges.get(&tx).map_or(0, |neighbors| neighbors.len())
+    }
+
+    pub fn neighbors(&self, tx: u64) -> Vec<u64> {
+        let mut neighbors: Vec<u64> = self
+            .edges
+            .get(&tx)
+            .map(|n| n.iter().copied().collect())
+            .unwrap_or_default();
+        neighbors.sort_unstable();
+        neighbors
+    }
+
+    /// Keys that make `tx1` and `tx2` conflict (WW, WR or RW). Empty when the
+    /// edge comes from unknown accesses only.
+    pub fn conflicting_keys(&self, tx1: u64, tx2: u64) -> Vec<Key> {
+        let (Some(a), Some(b)) = (self.nodes.get(&tx1), self.nodes.get(&tx2)) else {
+            return Vec::new();
+        };
+        let mut keys: Vec<Key> = a
+            .writes
+            .iter()
+            .filter(|k| b.writes.contains(k) || b.reads.contains(k))
+            .chain(a.reads.iter().filter(|k| b.writes.contains(k)))
+            .copied()
+            .collect::<AHashSet<Key>>()
+            .into_iter()
+            .collect();
+        keys.sort_unstable_by_key(|k| (k.address, k.slot));
+        keys
+    }
+
+    /// Connected components, largest first; ties are ordered by lowest id.
+    pub fn connected_components(&self) -> Vec<Vec<u64>> {
+        let mut visited: AHashSet<u64> = AHashSet::new();
+        let mut components = Vec::new();
+
+        for id in self.node_ids() {
+            if !visited.insert(id) {
+                continue;
+            }
+            let mut component = vec![id];
+            let mut stack = vec![id];
+            while let Some(current) = stack.pop() {
+                for &next in self.edges.get(&current).into_iter().flatten() {
+                    if visited.insert(next) {
+                        component.push(next);
+                        stack.push(next);
+                    }
+                }
+            }
+            component.sort_unstable();
+            components.push(component);
+        }
+
+        components.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+        components
+    }
+
+    /// Graphviz DOT with nodes colored by wave and edges labeled with the
+    /// conflicting keys.
+    pub fn to_dot(&self, waves: &[Vec<u64>]) -> String {
+        let wave_of = Self::wave_index(waves);
+        let mut dot = String::from("graph conflicts {\n  node [style=filled];\n");
+
+        for id in self.node_ids() {
+            match wave_of.get(&id) {
+                Some(&wave) => {
+                    let _ = writeln!(
+                        dot,
+                        "  {} [label=\"tx {}\\nwave {}\", fillcolor=\"{}\"];",
+                        id,
+                        id,
+                        wave,
+                        WAVE_COLORS[wave % WAVE_COLORS.len()]
+                    );
+                }
+                None => {
+                    let _ = writeln!(
+                        dot,
+                        "  {} [label=\"tx {}\", fillcolor=\"#cccccc\"];",
+                        id, id
+                    );
+                }
+            }
+        }
+        for (a, b) in self.edges() {
+            let _ = writeln!(
+                dot,
+                "  {} -- {} [label=\"{}\"];",
+                a,
+                b,
+                self.edge_label(a, b, "\\n")
+            );
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// GraphML with `wave`, `color` node attributes and a `keys` edge attribute.
+    pub fn to_graphml(&self, waves: &[Vec<u64>]) -> String {
+        let wave_of = Self::wave_index(waves);
+        let mut xml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"wave\" for=\"node\" attr.name=\"wave\" attr.type=\"int\"/>\n",
+            "  <key id=\"color\" for=\"node\" attr.name=\"color\" attr.type=\"string\"/>\n",
+            "  <key id=\"keys\" for=\"edge\" attr.name=\"keys\" attr.type=\"string\"/>\n",
+            "  <graph id=\"conflicts\" edgedefault=\"undirected\">\n",
+        ));
+
+        for id in self.node_ids() {
+            let _ = writeln!(xml, "    <node id=\"tx{}\">", id);
+            if let Some(&wave) = wave_of.get(&id) {
+                let _ = writeln!(xml, "      <data key=\"wave\">{}</data>", wave);
+                let _ = writeln!(
+                    xml,
+                    "      <data key=\"color\">{}</data>",
+                    WAVE_COLORS[wave % WAVE_COLORS.len()]
+                );
+            }
+            xml.push_str("    </node>\n");
+        }
+        for (a, b) in self.edges() {
+            let _ = writeln!(
+                xml,
+                "    <edge source=\"tx{}\" target=\"tx{}\">\n      <data key=\"keys\">{}</data>\n    </edge>",
+                a,
+                b,
+                self.edge_label(a, b, " ")
+            );
+        }
+
+        xml.push_str("  </graph>\n</graphml>\n");
+        xml
+    }
+
+    fn edge_label(&self, tx1: u64, tx2: u64, separator: &str) -> String {
+        self.conflicting_keys(tx1, tx2)
+            .iter()
+            .map(|k| k.to_string())
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    fn wave_index(waves: &[Vec<u64>]) -> AHashMap<u64, usize> {
+        waves
+            .iter()
+            .enumerate()
+            .flat_map(|(wave, ids)| ids.iter().map(move |&id| (id, wave)))
+            .collect()
+    }
+
     pub fn build(transactions: &[(u64, AccessSets)]) -> Self {
-        use crate::types::Key;
         let mut graph = Self::new();
 
         for (id, sets) in transactions {
@@ -75,6 +273,52 @@ impl ConflictGraph {
                 }
             }
         }
+
+        for (id, sets) in transactions {
+            if sets.unknown {
+                for (other, _) in transactions {
+                    if other != id {
+                        graph.add_edge(*id, *other);
+                    }
+                }
+            }
+        }
         graph
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graph_accessors_and_export() {
+        let shared = Key::new([1u8; 20], [1u8; 32]);
+        let mut writer = AccessSets::new();
+        writer.add_write(shared);
+        let mut reader = AccessSets::new();
+        reader.add_read(shared);
+        let mut other = AccessSets::new();
+        other.add_write(Key::new([2u8; 20], [2u8; 32]));
+
+        let graph = ConflictGraph::build(&[(0, writer), (1, reader), (2, other.clone())]);
+        assert_eq!(graph.edges(), vec![(0, 1)]);
+        assert_eq!(graph.neighbors(0), vec![1]);
+        assert_eq!(graph.conflicting_keys(0, 1), vec![shared]);
+        assert_eq!(graph.connected_components(), vec![vec![0, 1], vec![2]]);
+
+        let waves = vec![vec![0, 2], vec![1]];
+        let dot = graph.to_dot(&waves);
+        assert!(dot.contains("0 -- 1"));
+        assert!(dot.contains(&shared.to_string()));
+        let graphml = graph.to_graphml(&waves);
+        assert!(graphml.contains("<edge source=\"tx0\" target=\"tx1\">"));
+        assert!(graphml.contains("<data key=\"wave\">1</data>"));
+
+        let mut unknown = AccessSets::new();
+        unknown.unknown = true;
+        let graph = ConflictGraph::build(&[(0, AccessSets::new()), (1, unknown), (2, other)]);
+        assert_eq!(graph.edges(), vec![(0, 1), (1, 2)]);
+        assert!(graph.conflicting_keys(0, 1).is_empty());
+    }
+}
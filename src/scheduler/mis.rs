@@ -2,6 +2,7 @@ use super::{AccessListBuilder, ConflictGraph};
 use crate::types::{AccessSets, Block};
 use ahash::AHashSet;
 
+#[derive(Clone)]
 pub struct MIScheduler {
     max_wave_size: usize,
 }
@@ -22,11 +23,19 @@ impl MIScheduler {
             })
             .collect();
 
+        self.schedule_with_sets(block, &access_sets)
+    }
+
+    pub fn schedule_with_sets(
+        &self,
+        block: &Block,
+        access_sets: &[(u64, AccessSets)],
+    ) -> Vec<Vec<u64>> {
         if access_sets.is_empty() {
             return block.transactions.iter().map(|tx| vec![tx.id]).collect();
         }
 
-        let graph = ConflictGraph::build(&access_sets);
+        let graph = ConflictGraph::build(access_sets);
         let mut waves = Vec::new();
         let mut processed: AHashSet<u64> = AHashSet::new();
 
@@ -1,9 +1,17 @@
 pub mod access_oracle;
+pub mod builder;
+pub mod chain;
 pub mod conflict_graph;
 pub mod mis;
 pub mod parallel;
+pub mod report;
 
-pub use access_oracle::{AccessListBuilder, AccessOracle, HeuristicOracle};
+pub use access_oracle::{AccessListBuilder, AccessOracle, ExactOracle, HeuristicOracle};
+pub use builder::{BlockBuilder, BuiltBlock};
+pub use chain::{ChainBlockResult, ChainExecutionResult, ChainExecutor, PipelineStats, StageStats};
 pub use conflict_graph::ConflictGraph;
 pub use mis::MIScheduler;
 pub use parallel::{ParallelExecutionResult, ParallelExecutor};
+pub use report::{
+    export_conflicts_csv, export_conflicts_json, ConflictKind, ConflictReport, TxConflict,
+};
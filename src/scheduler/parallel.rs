@@ -1,23 +1,63 @@
+use super::report::{ConflictKind, ConflictReport, TxConflict};
 use super::{AccessListBuilder, MIScheduler};
-use crate::evm::{execute_transaction, ExecutionContext};
-use crate::storage::{KVStore, MemoryStore};
-use crate::types::{Block, ExecutionResult, Key};
-use ahash::AHashSet;
+use crate::evm::receipt::{build_receipts, Receipt};
+use crate::evm::{
+    calculate_blob_gas, check_block_gas, credit_coinbase, execute_transaction, BlockEnv,
+    ExecutionContext, GasSchedule,
+};
+use crate::storage::{KVStore, MemoryStore, OverlayStore};
+use crate::types::{
+    count_failures, AccountField, Block, ExecutionError, ExecutionResult, Key, U256,
+};
+use ahash::{AHashMap, AHashSet};
 use rayon::prelude::*;
-use std::sync::Arc;
-use tracing::{debug, info};
+use std::collections::BTreeMap;
+use tracing::{debug, info, warn};
 
 pub struct ParallelExecutionResult {
     pub storage: MemoryStore,
     pub results: Vec<ExecutionResult>,
+    /// One per transaction, in block order like `results`.
+    pub receipts: Vec<Receipt>,
+    /// Failed transactions per [`ExecutionError::kind`].
+    pub failures: BTreeMap<String, usize>,
     pub total_gas: u64,
+    pub blob_gas_used: u64,
     pub waves: Vec<Vec<u64>>,
+    pub conflict_reports: Vec<ConflictReport>,
+}
+
+#[derive(Default)]
+struct BlockProgress {
+    env: BlockEnv,
+    total_gas: u64,
+    blob_gas_used: u64,
+    results: AHashMap<u64, ExecutionResult>,
+    /// Transactions left out of the block by its gas or blob gas limit.
+    rejected: AHashSet<u64>,
+    /// Value of each slot before the block first wrote it.
+    original: AHashMap<Key, U256>,
+    /// Highest committed tx id that read / wrote each key.
+    last_reader: AHashMap<Key, u64>,
+    last_writer: AHashMap<Key, u64>,
+    order_violation: bool,
+    /// Priority fees committed but not yet paid to the coinbase. Kept out
+    /// of storage between waves, so fee payments only conflict with
+    /// transactions that read the coinbase balance.
+    coinbase_credit: U256,
+}
+
+impl BlockProgress {
+    fn coinbase_key(&self) -> Key {
+        Key::account(self.env.coinbase, AccountField::Balance)
+    }
 }
 
 pub struct ParallelExecutor {
     scheduler: MIScheduler,
     access_builder: AccessListBuilder,
     storage: MemoryStore,
+    schedule: GasSchedule,
 }
 
 impl ParallelExecutor {
@@ -30,13 +70,31 @@ impl ParallelExecutor {
             scheduler,
             access_builder,
             storage,
+            schedule: GasSchedule::default(),
         }
     }
 
+    pub fn with_gas_schedule(mut self, schedule: GasSchedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    pub fn gas_schedule(&self) -> &GasSchedule {
+        &self.schedule
+    }
+
     pub fn access_builder(&self) -> &AccessListBuilder {
         &self.access_builder
     }
 
+    pub fn storage(&self) -> &MemoryStore {
+        &self.storage
+    }
+
+    pub fn access_builder_mut(&mut self) -> &mut AccessListBuilder {
+        &mut self.access_builder
+    }
+
     pub fn execute_parallel(&mut self, block: &Block) -> ParallelExecutionResult {
         block
             .transactions
@@ -44,15 +102,40 @@ impl ParallelExecutor {
             .for_each(|tx| self.access_builder.estimate_before_execution(tx));
 
         let waves = self.scheduler.schedule(block, &self.access_builder);
-        let mut results_map = std::collections::HashMap::new();
-        let mut total_gas = 0;
-        let mut warm_keys: AHashSet<Key> = AHashSet::new();
+        self.execute_waves(block, waves)
+    }
+
+    /// Executes a block against a wave plan computed ahead of time, e.g. by a
+    /// pipelined planner. Conflicting transactions are still requeued.
+    pub fn execute_waves(
+        &mut self,
+        block: &Block,
+        waves: Vec<Vec<u64>>,
+    ) -> ParallelExecutionResult {
+        let mut progress = self.block_progress(block);
         let mut actual_waves = Vec::new();
+        let mut conflict_reports = Vec::new();
+        let mut round = 0;
+
+        // Blob gas does not depend on execution, so the blob limit can be
+        // applied up front assuming no transaction runs out of block gas.
+        let mut blob_gas_used = 0;
+        for tx in &block.transactions {
+            match check_block_gas(block, &self.schedule, 0, blob_gas_used, tx) {
+                Ok(()) => {
+                    blob_gas_used += calculate_blob_gas(&self.schedule, tx.metadata.blob_size)
+                }
+                Err(e) => Self::reject(tx.id, e, &mut progress),
+            }
+        }
 
-        use ahash::AHashMap;
         let tx_map: AHashMap<u64, &_> = block.transactions.iter().map(|tx| (tx.id, tx)).collect();
 
-        let mut pending: Vec<u64> = waves.into_iter().flatten().collect();
+        let mut pending: Vec<u64> = waves
+            .into_iter()
+            .flatten()
+            .filter(|id| !progress.rejected.contains(id))
+            .collect();
 
         while !pending.is_empty() {
             let wave_txs: Vec<_> = pending
@@ -66,17 +149,13 @@ impl ParallelExecutor {
             }
 
             let (committed, conflicting) = if wave_txs.len() == 1 {
-                self.execute_single_tx(
-                    wave_txs[0],
-                    &mut warm_keys,
-                    &mut total_gas,
-                    &mut results_map,
-                );
+                self.execute_single_tx(wave_txs[0], &mut progress);
                 (vec![wave_txs[0].id], vec![])
             } else {
                 let wave_ids: Vec<u64> = wave_txs.iter().map(|tx| tx.id).collect();
-                let conflicting =
-                    self.execute_wave(&wave_txs, &mut warm_keys, &mut total_gas, &mut results_map);
+                let report = self.execute_wave(&wave_txs, &mut progress);
+                let conflicting = report.aborted();
+                conflict_reports.push(ConflictReport { round, ..report });
                 let committed: Vec<u64> = wave_ids
                     .iter()
                     .filter(|id| !conflicting.contains(id))
@@ -85,6 +164,14 @@ impl ParallelExecutor {
                 (committed, conflicting)
             };
 
+            if progress.order_violation {
+                warn!(
+                    "Commit order violated in block {}, re-executing serially",
+                    block.number
+                );
+                return self.execute_serial_fallback(block, progress, conflict_reports);
+            }
+
             if !committed.is_empty() {
                 actual_waves.push(committed);
             }
@@ -92,59 +179,148 @@ impl ParallelExecutor {
                 info!("Requeueing {} conflicting txs", conflicting.len());
                 pending.extend(conflicting);
             }
+            round += 1;
         }
 
+        if self.inclusion_diverges(block, &progress) {
+            warn!(
+                "Block {} runs out of gas, re-executing serially",
+                block.number
+            );
+            return self.execute_serial_fallback(block, progress, conflict_reports);
+        }
+
+        self.pay_coinbase(&mut progress);
         let results: Vec<_> = block
             .transactions
             .iter()
-            .filter_map(|tx| results_map.remove(&tx.id))
+            .filter_map(|tx| progress.results.remove(&tx.id))
             .collect();
 
         ParallelExecutionResult {
             storage: self.storage.clone(),
+            receipts: build_receipts(&results),
+            failures: count_failures(&results),
             results,
-            total_gas,
+            total_gas: progress.total_gas,
+            blob_gas_used: progress.blob_gas_used,
             waves: actual_waves,
+            conflict_reports,
         }
     }
 
-    fn execute_single_tx(
+    fn block_progress(&self, block: &Block) -> BlockProgress {
+        BlockProgress {
+            env: BlockEnv::new(block, &self.schedule),
+            ..BlockProgress::default()
+        }
+    }
+
+    /// Applies the deferred coinbase credits, so that the next transactions
+    /// to run see them.
+    fn pay_coinbase(&mut self, progress: &mut BlockProgress) {
+        if progress.coinbase_credit.is_zero() {
+            return;
+        }
+        let key = progress.coinbase_key();
+        progress
+            .original
+            .entry(key)
+            .or_insert_with(|| self.storage.get(&key));
+        let credit = std::mem::take(&mut progress.coinbase_credit);
+        credit_coinbase(&mut self.storage, progress.env.coinbase, &credit);
+    }
+
+    fn reject(tx_id: u64, error: ExecutionError, progress: &mut BlockProgress) {
+        debug!("Transaction {} rejected: {}", tx_id, error);
+        progress.rejected.insert(tx_id);
+        progress
+            .results
+            .insert(tx_id, ExecutionResult::failure(tx_id, error));
+    }
+
+    /// Whether the transactions left out differ from those a serial run
+    /// would leave out. Waves decide inclusion optimistically; which
+    /// transactions fit is only known once the gas of all earlier ones is.
+    fn inclusion_diverges(&self, block: &Block, progress: &BlockProgress) -> bool {
+        let (mut gas_used, mut blob_gas_used) = (0, 0);
+        block.transactions.iter().any(|tx| {
+            let included =
+                check_block_gas(block, &self.schedule, gas_used, blob_gas_used, tx).is_ok();
+            if let Some(result) = progress.results.get(&tx.id).filter(|_| included) {
+                gas_used += result.gas_used;
+                blob_gas_used += result.blob_gas_used;
+            }
+            included == progress.rejected.contains(&tx.id)
+        })
+    }
+
+    /// Undoes the block and replays it in order. Used when a requeued
+    /// transaction touched keys that a later transaction had already
+    /// committed, which happens when its re-execution takes a different
+    /// branch, or when the block gas limit rejects a transaction.
+    fn execute_serial_fallback(
         &mut self,
-        tx: &crate::Transaction,
-        warm_keys: &mut AHashSet<Key>,
-        total_gas: &mut u64,
-        results_map: &mut std::collections::HashMap<u64, ExecutionResult>,
-    ) {
-        let mut ctx = ExecutionContext::new(self.storage.clone());
-        ctx.warm_keys = warm_keys.clone();
-        let result = execute_transaction(tx, &mut ctx);
+        block: &Block,
+        progress: BlockProgress,
+        conflict_reports: Vec<ConflictReport>,
+    ) -> ParallelExecutionResult {
+        for (key, value) in progress.original {
+            self.storage.set(key, value);
+        }
 
-        self.access_builder.record_after_execution(&result);
-        if result.success {
-            *total_gas += result.gas_used;
-            for key in &result.access_sets.writes {
-                self.storage.set(*key, ctx.storage.get(key));
+        let mut progress = self.block_progress(block);
+        for tx in &block.transactions {
+            let (gas_used, blob_gas_used) = (progress.total_gas, progress.blob_gas_used);
+            match check_block_gas(block, &self.schedule, gas_used, blob_gas_used, tx) {
+                Ok(()) => self.execute_single_tx(tx, &mut progress),
+                Err(e) => Self::reject(tx.id, e, &mut progress),
             }
-            *warm_keys = result.warm_keys.clone();
         }
-        results_map.insert(tx.id, result);
+        self.pay_coinbase(&mut progress);
+
+        let results: Vec<_> = block
+            .transactions
+            .iter()
+            .filter_map(|tx| progress.results.remove(&tx.id))
+            .collect();
+        ParallelExecutionResult {
+            storage: self.storage.clone(),
+            receipts: build_receipts(&results),
+            failures: count_failures(&results),
+            results,
+            total_gas: progress.total_gas,
+            blob_gas_used: progress.blob_gas_used,
+            waves: block.transactions.iter().map(|tx| vec![tx.id]).collect(),
+            conflict_reports,
+        }
+    }
+
+    fn execute_single_tx(&mut self, tx: &crate::Transaction, progress: &mut BlockProgress) {
+        self.pay_coinbase(progress);
+        let mut ctx = ExecutionContext::new(OverlayStore::new(self.storage.clone()))
+            .with_schedule(self.schedule)
+            .with_env(progress.env);
+        let result = execute_transaction(tx, &mut ctx);
+        self.commit(result, &ctx.storage, progress);
     }
 
     fn execute_wave(
         &mut self,
         wave_txs: &[&crate::Transaction],
-        warm_keys: &mut AHashSet<Key>,
-        total_gas: &mut u64,
-        results_map: &mut std::collections::HashMap<u64, ExecutionResult>,
-    ) -> Vec<u64> {
-        let storage_arc = Arc::new(self.storage.clone());
-        let wave_warm = Arc::new(warm_keys.clone());
-
-        let mut wave_results: Vec<(u64, ExecutionResult, MemoryStore)> = wave_txs
+        progress: &mut BlockProgress,
+    ) -> ConflictReport {
+        self.pay_coinbase(progress);
+        let storage = &self.storage;
+        let schedule = self.schedule;
+        let env = progress.env;
+
+        let mut wave_results: Vec<(u64, ExecutionResult, OverlayStore<MemoryStore>)> = wave_txs
             .par_iter()
             .map(|tx| {
-                let mut ctx = ExecutionContext::new((*storage_arc).clone());
-                ctx.warm_keys = (*wave_warm).clone();
+                let mut ctx = ExecutionContext::new(OverlayStore::new(storage.clone()))
+                    .with_schedule(schedule)
+                    .with_env(env);
                 let result = execute_transaction(tx, &mut ctx);
                 (tx.id, result, ctx.storage)
             })
@@ -152,7 +328,13 @@ impl ParallelExecutor {
 
         wave_results.sort_unstable_by_key(|(tx_id, _, _)| *tx_id);
 
-        let conflicting_txs = self.detect_conflicting_txs(&wave_results);
+        let conflicts = self.detect_conflicting_txs(&wave_results, progress.coinbase_key());
+        let report = ConflictReport {
+            round: 0,
+            wave_size: wave_txs.len(),
+            conflicts,
+        };
+        let conflicting_txs = report.aborted();
 
         if !conflicting_txs.is_empty() {
             debug!(
@@ -160,69 +342,803 @@ impl ParallelExecutor {
                 conflicting_txs.len(),
                 wave_txs.len()
             );
-
-            for (tx_id, result, tx_storage) in wave_results {
-                if conflicting_txs.contains(&tx_id) {
-                    continue;
-                }
-                self.access_builder.record_after_execution(&result);
-                if result.success {
-                    *total_gas += result.gas_used;
-                    for key in &result.access_sets.writes {
-                        self.storage.set(*key, tx_storage.get(key));
-                    }
-                    warm_keys.extend(&result.warm_keys);
-                }
-                results_map.insert(tx_id, result);
-            }
-            return conflicting_txs;
         }
 
         for (tx_id, result, tx_storage) in wave_results {
-            self.access_builder.record_after_execution(&result);
-            if result.success {
-                *total_gas += result.gas_used;
-                for key in &result.access_sets.writes {
-                    self.storage.set(*key, tx_storage.get(key));
-                }
-                warm_keys.extend(&result.warm_keys);
+            if conflicting_txs.contains(&tx_id) {
+                continue;
             }
-            results_map.insert(tx_id, result);
+            self.commit(result, &tx_storage, progress);
         }
 
-        vec![]
+        report
     }
 
+    fn commit(
+        &mut self,
+        result: ExecutionResult,
+        tx_storage: &OverlayStore<MemoryStore>,
+        progress: &mut BlockProgress,
+    ) {
+        let tx_id = result.tx_id;
+        let sets = &result.access_sets;
+        let later =
+            |map: &AHashMap<Key, u64>, key: &Key| map.get(key).is_some_and(|&id| id > tx_id);
+        // A fee payment is a write of the coinbase balance, which a later
+        // transaction that already read it has missed.
+        let coinbase = progress.coinbase_key();
+        if sets.reads.iter().any(|k| later(&progress.last_writer, k))
+            || sets
+                .writes
+                .iter()
+                .any(|k| later(&progress.last_writer, k) || later(&progress.last_reader, k))
+            || (!result.priority_fee.is_zero() && later(&progress.last_reader, &coinbase))
+        {
+            progress.order_violation = true;
+        }
+        for key in &sets.reads {
+            let reader = progress.last_reader.entry(*key).or_insert(tx_id);
+            *reader = (*reader).max(tx_id);
+        }
+        let fee_payment = (!result.priority_fee.is_zero()).then_some(coinbase);
+        for key in sets.writes.iter().chain(fee_payment.as_ref()) {
+            let writer = progress.last_writer.entry(*key).or_insert(tx_id);
+            *writer = (*writer).max(tx_id);
+        }
+
+        self.access_builder.record_after_execution(&result);
+        progress.total_gas += result.gas_used;
+        progress.blob_gas_used += result.blob_gas_used;
+        progress.coinbase_credit = progress.coinbase_credit.add(&result.priority_fee);
+        // Code is stored by hash, so deployments that did not stick leave
+        // nothing reachable behind.
+        for (hash, code) in tx_storage.new_code() {
+            self.storage.insert_code(*hash, code.clone());
+        }
+        // Reverted results only carry writes that outlive the revert.
+        for key in &result.access_sets.writes {
+            progress
+                .original
+                .entry(*key)
+                .or_insert_with(|| self.storage.get(key));
+            self.storage.set(*key, tx_storage.get(key));
+        }
+        progress.results.insert(tx_id, result);
+    }
+
+    /// Walks the wave in id order. A transaction aborts if it conflicts with
+    /// an earlier one in the wave, committed or aborted, so that nothing
+    /// commits ahead of a requeued transaction it depends on. Aborted
+    /// transactions block both their observed and their estimated keys.
+    /// Fee payments count as writes of the coinbase balance, so only its
+    /// readers are held back by them. Once a reader aborts, later fee payers
+    /// abort too: credits are paid out before the next wave, and its rerun
+    /// must not see theirs.
     fn detect_conflicting_txs(
         &self,
-        wave_results: &[(u64, ExecutionResult, MemoryStore)],
-    ) -> Vec<u64> {
-        let mut conflicting = AHashSet::new();
-        let mut committed_writes: AHashSet<Key> = AHashSet::new();
-        let mut committed_reads: AHashSet<Key> = AHashSet::new();
+        wave_results: &[(u64, ExecutionResult, OverlayStore<MemoryStore>)],
+        coinbase: Key,
+    ) -> Vec<TxConflict> {
+        let mut conflicts = Vec::new();
+        let mut committed_writes: AHashMap<Key, u64> = AHashMap::new();
+        let mut committed_reads: AHashMap<Key, u64> = AHashMap::new();
+        let mut aborted_coinbase_reader = None;
 
         for (tx_id, result, _) in wave_results {
-            let tx_writes: AHashSet<Key> = result.access_sets.writes.iter().copied().collect();
-            let tx_reads: AHashSet<Key> = result.access_sets.reads.iter().copied().collect();
+            let sets = &result.access_sets;
+            let mut by_cause: AHashMap<(u64, ConflictKind), Vec<Key>> = AHashMap::new();
+            let checks = [
+                (&sets.writes, &committed_writes, ConflictKind::WriteWrite),
+                (&sets.reads, &committed_writes, ConflictKind::ReadWrite),
+                (&sets.writes, &committed_reads, ConflictKind::WriteRead),
+            ];
+            for (keys, committed, kind) in checks {
+                for key in keys {
+                    if let Some(&other) = committed.get(key) {
+                        by_cause.entry((other, kind)).or_default().push(*key);
+                    }
+                }
+            }
+
+            for key in &sets.writes {
+                committed_writes.entry(*key).or_insert(*tx_id);
+            }
+            for key in &sets.reads {
+                committed_reads.entry(*key).or_insert(*tx_id);
+            }
+            if !result.priority_fee.is_zero() {
+                committed_writes.entry(coinbase).or_insert(*tx_id);
+                if let Some(reader) = aborted_coinbase_reader {
+                    by_cause
+                        .entry((reader, ConflictKind::WriteRead))
+                        .or_default()
+                        .push(coinbase);
+                }
+            }
 
-            let has_ww = tx_writes.iter().any(|k| committed_writes.contains(k));
-            let has_rw = tx_reads.iter().any(|k| committed_writes.contains(k));
-            let has_wr = tx_writes.iter().any(|k| committed_reads.contains(k));
+            if by_cause.is_empty() {
+                continue;
+            }
+
+            let estimated = self.access_builder.get_estimated(*tx_id);
+            if sets.reads.contains(&coinbase)
+                || estimated.is_some_and(|sets| sets.reads.contains(&coinbase))
+            {
+                aborted_coinbase_reader.get_or_insert(*tx_id);
+            }
+            if let Some(estimated) = estimated {
+                for key in &estimated.writes {
+                    committed_writes.entry(*key).or_insert(*tx_id);
+                }
+                for key in &estimated.reads {
+                    committed_reads.entry(*key).or_insert(*tx_id);
+                }
+            }
+
+            debug!(
+                "TX {} conflicts: {}",
+                tx_id,
+                by_cause
+                    .iter()
+                    .map(|((other, kind), keys)| format!(
+                        "{}x{} with TX {}",
+                        kind,
+                        keys.len(),
+                        other
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+
+            let mut tx_conflicts: Vec<TxConflict> = by_cause
+                .into_iter()
+                .map(|((caused_by, kind), mut keys)| {
+                    keys.sort_unstable_by_key(|k| (k.address, k.slot));
+                    TxConflict {
+                        tx_id: *tx_id,
+                        caused_by,
+                        kind,
+                        predicted: self.was_predicted(*tx_id, caused_by, kind, &keys),
+                        keys,
+                    }
+                })
+                .collect();
+            tx_conflicts.sort_unstable_by_key(|c| (c.caused_by, c.kind as u8));
+            conflicts.extend(tx_conflicts);
+        }
+
+        debug!(
+            "Total conflicting txs: {}",
+            conflicts
+                .iter()
+                .map(|c| c.tx_id)
+                .collect::<AHashSet<_>>()
+                .len()
+        );
+        conflicts
+    }
 
-            if has_ww || has_rw || has_wr {
-                debug!(
-                    "TX {} conflicts: WW={}, RW={}, WR={}",
-                    tx_id, has_ww, has_rw, has_wr
+    fn was_predicted(&self, tx_id: u64, caused_by: u64, kind: ConflictKind, keys: &[Key]) -> bool {
+        let (Some(aborted), Some(cause)) = (
+            self.access_builder.get_estimated(tx_id),
+            self.access_builder.get_estimated(caused_by),
+        ) else {
+            return false;
+        };
+        if aborted.unknown || cause.unknown {
+            return true;
+        }
+        let (mine, theirs) = match kind {
+            ConflictKind::WriteWrite => (&aborted.writes, &cause.writes),
+            ConflictKind::ReadWrite => (&aborted.reads, &cause.writes),
+            ConflictKind::WriteRead => (&aborted.writes, &cause.reads),
+        };
+        keys.iter()
+            .any(|key| mine.contains(key) && theirs.contains(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::{execute_serial, execute_serial_with_schedule};
+    use crate::generator::BlockGenerator;
+    use crate::scheduler::{AccessOracle, ExactOracle, HeuristicOracle};
+    use crate::storage::KVStore;
+    use crate::types::{Account, MicroOp, Transaction, TransactionMetadata, U256};
+
+    #[test]
+    fn test_parallel_matches_serial_under_conflicts() {
+        let block = BlockGenerator::new(80, 8, 0.8, 0.3, 3)
+            .with_rmw_ratio(1.0)
+            .with_branch_ratio(0.3)
+            .with_dynamic_ratio(0.3)
+            .generate();
+        let serial = execute_serial(&block, BlockGenerator::funded_storage(&block));
+
+        for miss_rate in [0.0, 0.5, 1.0] {
+            let access_builder =
+                AccessListBuilder::new(Box::new(HeuristicOracle::with_miss_rate(miss_rate)));
+            let mut executor = ParallelExecutor::new(
+                MIScheduler::new(10000),
+                access_builder,
+                BlockGenerator::funded_storage(&block),
+            );
+            let parallel = executor.execute_parallel(&block);
+
+            assert_eq!(parallel.total_gas, serial.total_gas);
+            for (p, s) in parallel.results.iter().zip(&serial.results) {
+                assert_eq!((p.tx_id, p.gas_used), (s.tx_id, s.gas_used));
+            }
+            let mut keys = serial.storage.keys();
+            let mut parallel_keys = parallel.storage.keys();
+            keys.sort_unstable_by_key(|k| (k.address, k.slot));
+            parallel_keys.sort_unstable_by_key(|k| (k.address, k.slot));
+            assert_eq!(parallel_keys, keys);
+            for key in keys {
+                assert_eq!(parallel.storage.get(&key), serial.storage.get(&key));
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_gas_limit_matches_serial() {
+        let block = BlockGenerator::new(120, 16, 0.5, 0.5, 7)
+            .with_rmw_ratio(0.5)
+            .generate();
+        let unlimited = execute_serial(&block, BlockGenerator::funded_storage(&block)).total_gas;
+        let block = block.with_gas_limit(unlimited / 2);
+        let serial = execute_serial(&block, BlockGenerator::funded_storage(&block));
+        assert!(serial.total_gas <= block.gas_limit);
+
+        let access_builder = AccessListBuilder::new(Box::new(HeuristicOracle::new()));
+        let mut executor = ParallelExecutor::new(
+            MIScheduler::new(10000),
+            access_builder,
+            BlockGenerator::funded_storage(&block),
+        );
+        let parallel = executor.execute_parallel(&block);
+
+        assert_eq!(parallel.total_gas, serial.total_gas);
+        let rejected = |results: &[ExecutionResult]| -> Vec<u64> {
+            results
+                .iter()
+                .filter(|r| r.error.as_ref().is_some_and(ExecutionError::is_rejection))
+                .map(|r| r.tx_id)
+                .collect()
+        };
+        assert!(!rejected(&serial.results).is_empty());
+        assert_eq!(rejected(&parallel.results), rejected(&serial.results));
+        assert_eq!(parallel.failures, serial.failures);
+        assert_eq!(parallel.storage.len(), serial.storage.len());
+        for key in serial.storage.keys() {
+            assert_eq!(parallel.storage.get(&key), serial.storage.get(&key));
+        }
+    }
+
+    #[test]
+    fn test_transfers_match_serial() {
+        // four senders passing value around, some running dry mid-block
+        let senders: Vec<[u8; 20]> = (1..=4u8).map(|i| [i; 20]).collect();
+        // clones of a MemoryStore share state, so each run gets its own
+        let funded = || {
+            let mut store = MemoryStore::new();
+            for sender in &senders {
+                let account = Account {
+                    balance: U256::from_u64(100),
+                    ..Account::default()
+                };
+                store.set_account(*sender, &account);
+            }
+            store
+        };
+        let transactions = (0..40u64)
+            .map(|id| {
+                let from = senders[id as usize % 4];
+                let to = senders[(id as usize * 3 + 1) % 4];
+                Transaction {
+                    id,
+                    reads: vec![],
+                    writes: vec![],
+                    gas_hint: 100_000,
+                    metadata: TransactionMetadata {
+                        program: vec![MicroOp::Transfer(to, U256::from_u64(15 + id % 4 * 20))],
+                        nonce: id / 4,
+                        from,
+                        ..Default::default()
+                    },
+                }
+            })
+            .collect();
+        let block = Block::new(1, transactions);
+        let serial = execute_serial(&block, funded());
+        assert!(serial.results.iter().any(|r| r.reverted));
+
+        for oracle in [
+            Box::new(ExactOracle) as Box<dyn AccessOracle>,
+            Box::new(HeuristicOracle::new()),
+        ] {
+            let mut executor = ParallelExecutor::new(
+                MIScheduler::new(10000),
+                AccessListBuilder::new(oracle),
+                funded(),
+            );
+            let parallel = executor.execute_parallel(&block);
+            assert_eq!(parallel.total_gas, serial.total_gas);
+            for (p, s) in parallel.results.iter().zip(&serial.results) {
+                assert_eq!((p.tx_id, p.reverted), (s.tx_id, s.reverted));
+            }
+            for sender in &senders {
+                let account = parallel.storage.account(sender);
+                assert_eq!(account, serial.storage.account(sender));
+                // reverted transfers still use up a nonce
+                assert_eq!(account.nonce, 10);
+            }
+        }
+    }
+
+    #[test]
+    fn test_nonce_mismatch_matches_serial() {
+        // two senders, each replaying its last nonce every third transaction
+        let mut next = [0u64; 2];
+        let transactions = (0..24u64)
+            .map(|id| {
+                let sender = (id % 2) as usize;
+                if id % 6 < 4 {
+                    next[sender] += 1;
+                }
+                Transaction {
+                    id,
+                    reads: vec![],
+                    writes: vec![],
+                    gas_hint: 50_000,
+                    metadata: TransactionMetadata {
+                        nonce: next[sender] - 1,
+                        from: [sender as u8 + 1; 20],
+                        ..Default::default()
+                    },
+                }
+            })
+            .collect();
+        let block = Block::new(1, transactions);
+        let serial = execute_serial(&block, MemoryStore::new());
+        assert_eq!(serial.failures.get("NonceMismatch"), Some(&8));
+
+        for oracle in [
+            Box::new(ExactOracle) as Box<dyn AccessOracle>,
+            Box::new(HeuristicOracle::new()),
+        ] {
+            let mut executor = ParallelExecutor::new(
+                MIScheduler::new(10000),
+                AccessListBuilder::new(oracle),
+                MemoryStore::new(),
+            );
+            let parallel = executor.execute_parallel(&block);
+            assert_eq!(parallel.failures, serial.failures);
+            for (p, s) in parallel.results.iter().zip(&serial.results) {
+                assert_eq!((p.tx_id, &p.error), (s.tx_id, &s.error));
+            }
+            for sender in [[1u8; 20], [2u8; 20]] {
+                assert_eq!(
+                    parallel.storage.account(&sender),
+                    serial.storage.account(&sender)
                 );
-                conflicting.insert(*tx_id);
-            } else {
-                committed_writes.extend(&tx_writes);
-                committed_reads.extend(&tx_reads);
             }
         }
+    }
+
+    #[test]
+    fn test_fee_payments_do_not_conflict() {
+        let coinbase = [0xcb; 20];
+        let block = |coinbase_reader: Option<u64>| {
+            let transactions = (0..20u64)
+                .map(|id| {
+                    let mut from = [0u8; 20];
+                    from[..8].copy_from_slice(&id.to_be_bytes());
+                    let program = match coinbase_reader {
+                        Some(reader) if reader == id => {
+                            vec![MicroOp::Transfer(coinbase, U256::from_u64(5))]
+                        }
+                        _ => vec![],
+                    };
+                    Transaction {
+                        id,
+                        reads: vec![],
+                        writes: vec![],
+                        gas_hint: 50_000,
+                        metadata: TransactionMetadata {
+                            program,
+                            from,
+                            max_fee_per_gas: U256::from_u64(20),
+                            max_priority_fee_per_gas: U256::from_u64(1 + id),
+                            ..Default::default()
+                        },
+                    }
+                })
+                .collect();
+            Block::new(1, transactions)
+                .with_coinbase(coinbase)
+                .with_base_fee(U256::from_u64(7))
+        };
+        let funded = |block: &Block| {
+            let mut store = MemoryStore::new();
+            for tx in &block.transactions {
+                let account = Account {
+                    balance: U256::from_u64(1_000_000),
+                    ..Account::default()
+                };
+                store.set_account(tx.metadata.from, &account);
+            }
+            store
+        };
+
+        for reader in [None, Some(10)] {
+            let block = block(reader);
+            let serial = execute_serial(&block, funded(&block));
+            let mut executor = ParallelExecutor::new(
+                MIScheduler::new(10000),
+                AccessListBuilder::new(Box::new(ExactOracle)),
+                funded(&block),
+            );
+            let parallel = executor.execute_parallel(&block);
+
+            assert!(parallel.results.iter().all(|r| r.success));
+            assert_eq!(parallel.storage.len(), serial.storage.len());
+            for key in serial.storage.keys() {
+                assert_eq!(parallel.storage.get(&key), serial.storage.get(&key));
+            }
+            if reader.is_none() {
+                // every transaction pays the coinbase, yet they all run at once
+                assert_eq!(parallel.waves.len(), 1);
+                assert!(parallel.conflict_reports[0].conflicts.is_empty());
+            }
+            // the reader is requeued, not the whole block replayed
+            assert!(parallel.waves.len() < block.transactions.len());
+        }
+    }
+
+    #[test]
+    fn test_block_context_does_not_conflict() {
+        let coinbase = [0xcb; 20];
+        let block = |coinbase_reader: Option<u64>| {
+            let transactions = (0..20u64)
+                .map(|id| {
+                    let from = [id as u8 + 1; 20];
+                    let slot = |n: u8| Key::new(from, [n; 32]);
+                    let mut program = vec![
+                        MicroOp::Number,
+                        MicroOp::SStoreTop(slot(1)),
+                        MicroOp::Timestamp,
+                        MicroOp::SStoreTop(slot(2)),
+                        MicroOp::ChainId,
+                        MicroOp::SStoreTop(slot(3)),
+                        MicroOp::BaseFee,
+                        MicroOp::SStoreTop(slot(4)),
+                        MicroOp::Coinbase,
+                        MicroOp::SStoreTop(slot(5)),
+                        MicroOp::Caller,
+                        MicroOp::SStoreTop(slot(6)),
+                    ];
+                    if coinbase_reader == Some(id) {
+                        program.extend([
+                            MicroOp::Push(U256::from_address(&coinbase)),
+                            MicroOp::Balance,
+                            MicroOp::SStoreTop(slot(7)),
+                        ]);
+                    }
+                    Transaction {
+                        id,
+                        reads: vec![],
+                        writes: vec![],
+                        gas_hint: 200_000,
+                        metadata: TransactionMetadata {
+                            program,
+                            from,
+                            max_fee_per_gas: U256::from_u64(20),
+                            max_priority_fee_per_gas: U256::from_u64(1 + id),
+                            ..Default::default()
+                        },
+                    }
+                })
+                .collect();
+            Block::new(9, transactions)
+                .with_coinbase(coinbase)
+                .with_base_fee(U256::from_u64(7))
+                .with_chain_id(5)
+        };
+        let funded = |block: &Block| {
+            let mut store = MemoryStore::new();
+            for tx in &block.transactions {
+                let account = Account {
+                    balance: U256::from_u64(10_000_000),
+                    ..Account::default()
+                };
+                store.set_account(tx.metadata.from, &account);
+            }
+            store
+        };
+
+        for reader in [None, Some(10)] {
+            let block = block(reader);
+            let serial = execute_serial(&block, funded(&block));
+            let sender = block.transactions[3].metadata.from;
+            let stored = |n: u8| serial.storage.get(&Key::new(sender, [n; 32]));
+            assert_eq!(stored(1), U256::from_u64(9));
+            assert_eq!(stored(2), U256::from_u64(block.timestamp));
+            assert_eq!(stored(3), U256::from_u64(5));
+            assert_eq!(stored(4), U256::from_u64(7));
+            assert_eq!(stored(5), U256::from_address(&coinbase));
+            assert_eq!(stored(6), U256::from_address(&sender));
+            if let Some(reader) = reader {
+                // sees the fees of the transactions before it
+                let reader = block.transactions[reader as usize].metadata.from;
+                assert!(!serial.storage.get(&Key::new(reader, [7; 32])).is_zero());
+            }
+
+            let mut executor = ParallelExecutor::new(
+                MIScheduler::new(10000),
+                AccessListBuilder::new(Box::new(ExactOracle)),
+                funded(&block),
+            );
+            let parallel = executor.execute_parallel(&block);
+            if reader.is_none() {
+                assert_eq!(parallel.waves.len(), 1);
+            }
+            assert!(parallel.results.iter().all(|r| r.success));
+            assert_eq!(parallel.storage.len(), serial.storage.len());
+            for key in serial.storage.keys() {
+                assert_eq!(parallel.storage.get(&key), serial.storage.get(&key));
+            }
+        }
+    }
+
+    #[test]
+    fn test_contract_calls_match_serial() {
+        // half of the transactions bump a shared counter through its contract
+        let counter = [0xc0; 20];
+        let key = Key::new(counter, [0u8; 32]);
+        let storage = || {
+            let mut store = MemoryStore::new();
+            crate::evm::deploy_code(
+                &mut store,
+                counter,
+                vec![
+                    MicroOp::SLoad(key),
+                    MicroOp::Add(U256::ONE),
+                    MicroOp::SStoreTop(key),
+                ],
+            );
+            store
+        };
+        let transactions = (0..20u64)
+            .map(|id| Transaction {
+                id,
+                reads: vec![],
+                writes: vec![],
+                gas_hint: 100_000,
+                metadata: TransactionMetadata {
+                    program: vec![MicroOp::SStore(
+                        Key::new([id as u8; 20], [1u8; 32]),
+                        U256::ONE,
+                    )],
+                    from: [id as u8; 20],
+                    to: (id % 2 == 0).then_some(counter),
+                    ..Default::default()
+                },
+            })
+            .collect();
+        let block = Block::new(1, transactions);
+        let serial = execute_serial(&block, storage());
+        assert_eq!(serial.storage.get(&key), U256::from_u64(10));
+
+        let mut executor = ParallelExecutor::new(
+            MIScheduler::new(10000),
+            AccessListBuilder::new(Box::new(ExactOracle)),
+            storage(),
+        );
+        let parallel = executor.execute_parallel(&block);
+        assert_eq!(parallel.total_gas, serial.total_gas);
+        assert_eq!(parallel.storage.len(), serial.storage.len());
+        for key in serial.storage.keys() {
+            assert_eq!(parallel.storage.get(&key), serial.storage.get(&key));
+        }
+    }
+
+    #[test]
+    fn test_deployments_match_serial() {
+        // one transaction deploys a counter, the next ones bump it
+        let deployer = [0xd0; 20];
+        let salt = U256::from_u64(7);
+        let counted = Key::new([0xee; 20], [0u8; 32]);
+        let counter = vec![
+            MicroOp::SLoad(counted),
+            MicroOp::Add(U256::ONE),
+            MicroOp::SStoreTop(counted),
+        ];
+        let address =
+            crate::evm::call::create2_address(&deployer, &salt, &crate::evm::code_hash(&counter));
+        let tx = |id: u64, from: [u8; 20], to: Option<[u8; 20]>, program| Transaction {
+            id,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 1_000_000,
+            metadata: TransactionMetadata {
+                program,
+                from,
+                to,
+                ..Default::default()
+            },
+        };
+        let deploy = vec![
+            MicroOp::Push(salt),
+            MicroOp::Push(U256::ZERO),
+            MicroOp::Create2(counter),
+        ];
+        let mut transactions = vec![
+            tx(0, deployer, None, deploy.clone()),
+            // same salt and code: collides
+            tx(1, deployer, None, deploy),
+        ];
+        transactions[1].metadata.nonce = 1;
+        for id in 2..8u64 {
+            transactions.push(tx(id, [id as u8; 20], Some(address), vec![]));
+        }
+        let block = Block::new(1, transactions);
+        let serial = execute_serial(&block, MemoryStore::new());
+        assert!(serial.results.iter().all(|r| r.success));
+        let code_key = Key::account(address, crate::types::AccountField::CodeHash);
+        assert!(!serial.storage.get(&code_key).is_zero());
+        assert_eq!(serial.storage.get(&counted), U256::from_u64(6));
+
+        let mut executor = ParallelExecutor::new(
+            MIScheduler::new(10000),
+            AccessListBuilder::new(Box::new(ExactOracle)),
+            MemoryStore::new(),
+        );
+        let parallel = executor.execute_parallel(&block);
+        assert_eq!(parallel.total_gas, serial.total_gas);
+        assert_eq!(parallel.storage.len(), serial.storage.len());
+        for key in serial.storage.keys() {
+            assert_eq!(parallel.storage.get(&key), serial.storage.get(&key));
+        }
+        let hash = parallel.storage.get(&code_key);
+        assert!(parallel.storage.code(&hash).is_some());
+    }
 
-        let result: Vec<u64> = conflicting.into_iter().collect();
-        debug!("Total conflicting txs: {}", result.len());
-        result
+    #[test]
+    fn test_receipts_match_serial() {
+        // the shared counter spreads transactions over several waves, so
+        // they finish out of block order
+        let counter = Key::new([0xc0; 20], [0u8; 32]);
+        let transactions = (0..12u64)
+            .map(|id| {
+                let mut program = vec![
+                    MicroOp::Push(U256::from_u64(id)),
+                    MicroOp::Push(U256::from_u64(id)),
+                    MicroOp::Push(U256::ZERO),
+                    MicroOp::Log1,
+                ];
+                if id % 2 == 0 {
+                    program.extend([
+                        MicroOp::SLoad(counter),
+                        MicroOp::Add(U256::ONE),
+                        MicroOp::SStoreTop(counter),
+                    ]);
+                }
+                if id % 3 == 0 {
+                    program.push(MicroOp::Revert);
+                }
+                Transaction {
+                    id,
+                    reads: vec![],
+                    writes: vec![],
+                    gas_hint: 100_000,
+                    metadata: TransactionMetadata {
+                        program,
+                        from: [id as u8; 20],
+                        ..Default::default()
+                    },
+                }
+            })
+            .collect();
+        let block = Block::new(1, transactions);
+        let serial = execute_serial(&block, MemoryStore::new());
+        assert_eq!(serial.receipts.len(), 12);
+        assert_eq!(
+            serial.receipts.last().unwrap().cumulative_gas_used,
+            serial.total_gas
+        );
+        for receipt in &serial.receipts {
+            assert_eq!(receipt.status, receipt.tx_id % 3 != 0);
+            assert_eq!(receipt.logs.len(), receipt.status as usize);
+        }
+
+        let mut executor = ParallelExecutor::new(
+            MIScheduler::new(10000),
+            AccessListBuilder::new(Box::new(ExactOracle)),
+            MemoryStore::new(),
+        );
+        let parallel = executor.execute_parallel(&block);
+        assert!(parallel.waves.len() > 1);
+        assert_eq!(parallel.receipts, serial.receipts);
+    }
+
+    #[test]
+    fn test_blob_limit_matches_serial() {
+        let mut block = BlockGenerator::new(60, 200, 0.2, 0.3, 11).generate();
+        for tx in block.transactions.iter_mut().step_by(4) {
+            tx.metadata.blob_size = 2 * crate::evm::BYTES_PER_BLOB;
+        }
+        let serial = execute_serial(&block, BlockGenerator::funded_storage(&block));
+
+        let access_builder = AccessListBuilder::new(Box::new(HeuristicOracle::new()));
+        let mut executor = ParallelExecutor::new(
+            MIScheduler::new(10000),
+            access_builder,
+            BlockGenerator::funded_storage(&block),
+        );
+        let parallel = executor.execute_parallel(&block);
+
+        assert_eq!(parallel.blob_gas_used, serial.blob_gas_used);
+        assert_eq!(
+            parallel.blob_gas_used,
+            GasSchedule::default().max_blob_gas_per_block
+        );
+        assert_eq!(parallel.total_gas, serial.total_gas);
+        for (p, s) in parallel.results.iter().zip(&serial.results) {
+            assert_eq!(p.tx_id, s.tx_id);
+            assert_eq!(
+                (p.blob_gas_used, &p.blob_fee),
+                (s.blob_gas_used, &s.blob_fee)
+            );
+            assert_eq!(p.error, s.error);
+        }
+        // rejections are known up front, so no serial replay was needed
+        assert!(parallel.waves.len() < block.transactions.len());
+    }
+
+    #[test]
+    fn test_parallel_respects_gas_schedule() {
+        // each tx sets and clears its own slot: 0 -> 1 -> 0
+        let transactions = (0..8u8)
+            .map(|i| {
+                let key = Key::new([i; 20], [i; 32]);
+                Transaction {
+                    id: i as u64,
+                    reads: vec![],
+                    writes: vec![key],
+                    gas_hint: 100000,
+                    metadata: TransactionMetadata {
+                        program: vec![
+                            MicroOp::SStore(key, U256::ONE),
+                            MicroOp::SStore(key, U256::ZERO),
+                        ],
+                        from: [i; 20],
+                        ..Default::default()
+                    },
+                }
+            })
+            .collect();
+        let block = Block::new(1, transactions);
+
+        for schedule in [GasSchedule::berlin(), GasSchedule::cancun()] {
+            let serial = execute_serial_with_schedule(&block, MemoryStore::new(), &schedule);
+            let access_builder = AccessListBuilder::new(Box::new(HeuristicOracle::new()));
+            let mut executor =
+                ParallelExecutor::new(MIScheduler::new(10000), access_builder, MemoryStore::new())
+                    .with_gas_schedule(schedule);
+            let parallel = executor.execute_parallel(&block);
+
+            assert_eq!(executor.gas_schedule(), &schedule);
+            assert_eq!(parallel.total_gas, serial.total_gas);
+            let gross =
+                schedule.tx_base + schedule.cold_sload + schedule.sstore_set + schedule.warm_sload;
+            // Berlin refunds the whole set, London on caps it at a fifth
+            let refund = (schedule.sstore_set - schedule.warm_sload)
+                .min(gross / schedule.max_refund_quotient);
+            for result in &parallel.results {
+                assert_eq!(result.gas_refund, refund);
+            }
+        }
     }
 }
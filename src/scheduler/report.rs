@@ -0,0 +1,124 @@
+use crate::types::Key;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::Write as _;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ConflictKind {
+    /// Both transactions wrote the key.
+    WriteWrite,
+    /// The aborted transaction read a key written by the earlier one.
+    ReadWrite,
+    /// The aborted transaction wrote a key read by the earlier one.
+    WriteRead,
+}
+
+impl fmt::Display for ConflictKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            ConflictKind::WriteWrite => "WW",
+            ConflictKind::ReadWrite => "RW",
+            ConflictKind::WriteRead => "WR",
+        };
+        f.write_str(label)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxConflict {
+    pub tx_id: u64,
+    pub caused_by: u64,
+    pub kind: ConflictKind,
+    pub keys: Vec<Key>,
+    pub predicted: bool,
+}
+
+/// Why transactions were requeued in one execution round.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConflictReport {
+    pub round: usize,
+    pub wave_size: usize,
+    pub conflicts: Vec<TxConflict>,
+}
+
+impl ConflictReport {
+    pub fn aborted(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.conflicts.iter().map(|c| c.tx_id).collect();
+        ids.dedup();
+        ids
+    }
+}
+
+pub fn export_conflicts_json(reports: &[ConflictReport], path: &str) -> Result<(), std::io::Error> {
+    let json = serde_json::to_string_pretty(reports)?;
+    std::fs::write(path, json)?;
+    Ok(())
+}
+
+/// Writes one row per conflicting key.
+pub fn export_conflicts_csv(reports: &[ConflictReport], path: &str) -> Result<(), std::io::Error> {
+    let mut csv = String::from("round,tx_id,caused_by,kind,key,predicted\n");
+    for report in reports {
+        for conflict in &report.conflicts {
+            for key in &conflict.keys {
+                let _ = writeln!(
+                    csv,
+                    "{},{},{},{},{},{}",
+                    report.round,
+                    conflict.tx_id,
+                    conflict.caused_by,
+                    conflict.kind,
+                    key,
+                    conflict.predicted
+                );
+            }
+        }
+    }
+    std::fs::write(path, csv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{AccessListBuilder, HeuristicOracle, MIScheduler, ParallelExecutor};
+    use crate::storage::MemoryStore;
+    use crate::types::{Block, Key, MicroOp, Transaction, TransactionMetadata, U256};
+
+    fn tx(id: u64, program: Vec<MicroOp>) -> Transaction {
+        Transaction {
+            id,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 100000,
+            metadata: TransactionMetadata {
+                program,
+                from: [id as u8; 20],
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_conflict_report() {
+        let key = Key::new([1u8; 20], [1u8; 32]);
+        let block = Block::new(
+            1,
+            vec![
+                tx(0, vec![MicroOp::SStore(key, U256::from_u64(1))]),
+                tx(1, vec![MicroOp::SLoad(key)]),
+            ],
+        );
+        let access_builder = AccessListBuilder::new(Box::new(HeuristicOracle::with_miss_rate(1.0)));
+        let mut executor =
+            ParallelExecutor::new(MIScheduler::new(10000), access_builder, MemoryStore::new());
+        let result = executor.execute_parallel(&block);
+
+        let report = &result.conflict_reports[0];
+        assert_eq!(report.aborted(), vec![1]);
+        let conflict = &report.conflicts[0];
+        assert_eq!(conflict.caused_by, 0);
+        assert_eq!(conflict.kind, ConflictKind::ReadWrite);
+        assert_eq!(conflict.keys, vec![key]);
+        assert!(!conflict.predicted);
+    }
+}
@@ -1,4 +1,4 @@
-use super::KVStore;
+use super::{Code, KVStore};
 use crate::types::{Key, U256};
 use ahash::AHashMap;
 use std::sync::{Arc, Mutex, PoisonError};
@@ -6,12 +6,14 @@ use std::sync::{Arc, Mutex, PoisonError};
 #[derive(Clone)]
 pub struct MemoryStore {
     inner: Arc<Mutex<AHashMap<Key, U256>>>,
+    code: Arc<Mutex<AHashMap<U256, Code>>>,
 }
 
 impl MemoryStore {
     pub fn new() -> Self {
         Self {
             inner: Arc::new(Mutex::new(AHashMap::new())),
+            code: Arc::new(Mutex::new(AHashMap::new())),
         }
     }
 }
@@ -32,11 +34,29 @@ impl KVStore for MemoryStore {
             .unwrap_or(U256::ZERO)
     }
 
+    /// Zero is the value of an empty slot, so storing it removes the key.
     fn set(&mut self, key: Key, value: U256) {
-        self.inner
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if value.is_zero() {
+            inner.remove(&key);
+        } else {
+            inner.insert(key, value);
+        }
+    }
+
+    fn code(&self, code_hash: &U256) -> Option<Code> {
+        self.code
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(code_hash)
+            .cloned()
+    }
+
+    fn insert_code(&mut self, code_hash: U256, code: Code) {
+        self.code
             .lock()
             .unwrap_or_else(PoisonError::into_inner)
-            .insert(key, value);
+            .insert(code_hash, code);
     }
 
     fn keys(&self) -> Vec<Key> {
@@ -59,6 +79,7 @@ impl KVStore for MemoryStore {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::Account;
 
     #[test]
     fn test_memory_store_operations() {
@@ -68,5 +89,28 @@ mod tests {
         store.set(key, U256::from_u64(100));
         assert_eq!(store.get(&key), U256::from_u64(100));
         assert_eq!(store.len(), 1);
+
+        store.set(key, U256::ZERO);
+        assert_eq!(store.get(&key), U256::ZERO);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_account_roundtrip() {
+        let mut store = MemoryStore::new();
+        let address = [7u8; 20];
+        assert_eq!(store.account(&address), Account::default());
+
+        let account = Account {
+            balance: U256::from_u64(1000),
+            nonce: 3,
+            code_hash: U256::ZERO,
+        };
+        store.set_account(address, &account);
+        assert_eq!(store.account(&address), account);
+        // zero fields are not stored, and accounts stay clear of slot keys
+        assert_eq!(store.len(), 2);
+        assert!(store.keys().iter().all(|key| key.is_account()));
+        assert!(!Key::new(address, [0u8; 32]).is_account());
     }
 }
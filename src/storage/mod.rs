@@ -1,14 +1,48 @@
-use crate::types::{Key, U256};
+use crate::types::{Account, AccountField, Key, MicroOp, U256};
+use std::sync::Arc;
 
 pub mod memory;
+pub mod overlay;
 pub use memory::MemoryStore;
+pub use overlay::OverlayStore;
+
+/// Contract code, shared between the stores that hold it.
+pub type Code = Arc<Vec<MicroOp>>;
 
 pub trait KVStore: Clone + Send + Sync {
     fn get(&self, key: &Key) -> U256;
     fn set(&mut self, key: Key, value: U256);
+    /// Code is addressed by its hash, which accounts refer to through
+    /// their code hash field.
+    fn code(&self, code_hash: &U256) -> Option<Code>;
+    fn insert_code(&mut self, code_hash: U256, code: Code);
     fn keys(&self) -> Vec<Key>;
     fn len(&self) -> usize;
     fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    fn account(&self, address: &[u8; 20]) -> Account {
+        let field = |field| self.get(&Key::account(*address, field));
+        Account {
+            balance: field(AccountField::Balance),
+            nonce: field(AccountField::Nonce).to_u64().unwrap_or(u64::MAX),
+            code_hash: field(AccountField::CodeHash),
+        }
+    }
+
+    fn set_account(&mut self, address: [u8; 20], account: &Account) {
+        self.set(
+            Key::account(address, AccountField::Balance),
+            account.balance,
+        );
+        self.set(
+            Key::account(address, AccountField::Nonce),
+            U256::from_u64(account.nonce),
+        );
+        self.set(
+            Key::account(address, AccountField::CodeHash),
+            account.code_hash,
+        );
+    }
 }
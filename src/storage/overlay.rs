@@ -0,0 +1,111 @@
+use super::{Code, KVStore};
+use crate::types::{Key, U256};
+use ahash::AHashMap;
+
+/// Buffers writes on top of a base store, which is only ever read. Lets
+/// transactions of a wave run against the same committed state in isolation.
+#[derive(Clone)]
+pub struct OverlayStore<S: KVStore> {
+    base: S,
+    writes: AHashMap<Key, U256>,
+    code: AHashMap<U256, Code>,
+}
+
+impl<S: KVStore> OverlayStore<S> {
+    pub fn new(base: S) -> Self {
+        Self {
+            base,
+            writes: AHashMap::new(),
+            code: AHashMap::new(),
+        }
+    }
+
+    pub fn writes(&self) -> &AHashMap<Key, U256> {
+        &self.writes
+    }
+
+    /// Code inserted on top of the base store.
+    pub fn new_code(&self) -> &AHashMap<U256, Code> {
+        &self.code
+    }
+}
+
+impl<S: KVStore> KVStore for OverlayStore<S> {
+    fn get(&self, key: &Key) -> U256 {
+        match self.writes.get(key) {
+            Some(value) => *value,
+            None => self.base.get(key),
+        }
+    }
+
+    fn set(&mut self, key: Key, value: U256) {
+        self.writes.insert(key, value);
+    }
+
+    fn code(&self, code_hash: &U256) -> Option<Code> {
+        match self.code.get(code_hash) {
+            Some(code) => Some(Code::clone(code)),
+            None => self.base.code(code_hash),
+        }
+    }
+
+    fn insert_code(&mut self, code_hash: U256, code: Code) {
+        self.code.insert(code_hash, code);
+    }
+
+    /// Keys holding a value once the writes are applied. A write of zero
+    /// empties the slot, as it would in the base store.
+    fn keys(&self) -> Vec<Key> {
+        let mut keys = self.base.keys();
+        keys.extend(self.writes.keys().copied());
+        keys.sort_unstable_by_key(|k| (k.address, k.slot));
+        keys.dedup();
+        keys.retain(|key| !self.get(key).is_zero());
+        keys
+    }
+
+    fn len(&self) -> usize {
+        self.keys().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStore;
+
+    #[test]
+    fn test_overlay_isolation() {
+        let mut base = MemoryStore::new();
+        let key = Key::new([1u8; 20], [1u8; 32]);
+        base.set(key, U256::from_u64(1));
+
+        let mut overlay = OverlayStore::new(base.clone());
+        overlay.set(key, U256::from_u64(2));
+        overlay.set(Key::new([2u8; 20], [2u8; 32]), U256::ONE);
+
+        assert_eq!(overlay.get(&key), U256::from_u64(2));
+        assert_eq!(base.get(&key), U256::from_u64(1));
+        assert_eq!(overlay.len(), 2);
+    }
+
+    #[test]
+    fn test_overlay_zero_writes() {
+        let key = |n: u8| Key::new([n; 20], [n; 32]);
+        let mut base = MemoryStore::new();
+        base.set(key(1), U256::ONE);
+        base.set(key(2), U256::ONE);
+
+        let mut overlay = OverlayStore::new(base.clone());
+        overlay.set(key(1), U256::ZERO);
+        overlay.set(key(3), U256::ZERO);
+        overlay.set(key(4), U256::ONE);
+        assert_eq!(overlay.keys(), [key(2), key(4)]);
+        assert_eq!(overlay.len(), 2);
+
+        for (key, value) in overlay.writes().clone() {
+            base.set(key, value);
+        }
+        assert_eq!(base.len(), overlay.len());
+    }
+}
@@ -1,5 +1,6 @@
 use ahash::AHashSet;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -12,6 +13,44 @@ impl Key {
     pub fn new(address: [u8; 20], slot: [u8; 32]) -> Self {
         Self { address, slot }
     }
+
+    /// Key for stack operands: the address is the low 20 bytes of `address`.
+    pub fn from_words(address: &U256, slot: &U256) -> Self {
+        let mut addr = [0u8; 20];
+        addr.copy_from_slice(&address.to_be_bytes()[12..]);
+        Self::new(addr, slot.to_be_bytes())
+    }
+
+    /// Key of one field of the account at `address`.
+    pub fn account(address: [u8; 20], field: AccountField) -> Self {
+        let mut slot = ACCOUNT_SLOT_PREFIX;
+        slot[31] = field as u8;
+        Self::new(address, slot)
+    }
+
+    /// Whether this key holds account state rather than a storage slot.
+    pub fn is_account(&self) -> bool {
+        self.slot[..31] == ACCOUNT_SLOT_PREFIX[..31]
+    }
+}
+
+/// Account fields share the `KVStore` with storage slots. They live under
+/// slots starting with 31 `0xff` bytes, which storage ops refuse to touch.
+const ACCOUNT_SLOT_PREFIX: [u8; 32] = [0xff; 32];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccountField {
+    Balance = 0,
+    Nonce = 1,
+    CodeHash = 2,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Account {
+    pub balance: U256,
+    pub nonce: u64,
+    /// Zero for accounts without code.
+    pub code_hash: U256,
 }
 
 impl fmt::Display for Key {
@@ -25,77 +64,634 @@ impl fmt::Display for Key {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct U256(pub [u8; 32]);
+/// 256-bit unsigned word stored as four little-endian `u64` limbs. Ordering,
+/// hashing and serialization follow the big-endian byte form. Arithmetic wraps
+/// modulo 2^256 and the `s*` methods interpret values as two's complement, as
+/// the EVM does.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct U256([u64; 4]);
 
 impl U256 {
-    pub const ZERO: U256 = U256([0u8; 32]);
-    pub const ONE: U256 = {
+    pub const ZERO: U256 = U256([0; 4]);
+    pub const ONE: U256 = U256([1, 0, 0, 0]);
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    pub const fn from_limbs(limbs: [u64; 4]) -> Self {
+        U256(limbs)
+    }
+
+    /// Little-endian 64-bit limbs.
+    pub const fn as_limbs(&self) -> &[u64; 4] {
+        &self.0
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let start = 32 - (i + 1) * 8;
+            let mut word = [0u8; 8];
+            word.copy_from_slice(&bytes[start..start + 8]);
+            *limb = u64::from_be_bytes(word);
+        }
+        U256(limbs)
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 32] {
         let mut bytes = [0u8; 32];
-        bytes[31] = 1;
-        U256(bytes)
-    };
+        for (i, limb) in self.0.iter().enumerate() {
+            let start = 32 - (i + 1) * 8;
+            bytes[start..start + 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
 
     pub fn from_u64(val: u64) -> Self {
-        let mut bytes = [0u8; 32];
-        bytes[24..32].copy_from_slice(&val.to_be_bytes());
-        U256(bytes)
+        U256([val, 0, 0, 0])
+    }
+
+    /// An address as a stack word, in the low 20 bytes.
+    pub fn from_address(address: &[u8; 20]) -> Self {
+        let mut word = [0u8; 32];
+        word[12..].copy_from_slice(address);
+        Self::from_be_bytes(word)
     }
 
     pub fn to_u64(&self) -> Option<u64> {
-        // Check if high bytes are zero
-        if self.0[..24].iter().any(|&b| b != 0) {
+        // Check if high limbs are zero
+        if self.0[1..].iter().any(|&limb| limb != 0) {
             return None;
         }
-        let mut bytes = [0u8; 8];
-        bytes.copy_from_slice(&self.0[24..32]);
-        Some(u64::from_be_bytes(bytes))
+        Some(self.0[0])
     }
 
     pub fn add(&self, other: &U256) -> U256 {
-        let mut result = [0u8; 32];
-        let mut carry = 0u16;
-        for i in (0..32).rev() {
-            let sum = self.0[i] as u16 + other.0[i] as u16 + carry;
-            result[i] = (sum & 0xFF) as u8;
-            carry = sum >> 8;
+        let mut result = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (sum, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
         }
         U256(result)
     }
 
     pub fn sub(&self, other: &U256) -> U256 {
-        let mut result = [0u8; 32];
-        let mut borrow = 0i16;
-        for i in (0..32).rev() {
-            let diff = self.0[i] as i16 - other.0[i] as i16 - borrow;
-            if diff < 0 {
-                result[i] = (diff + 256) as u8;
-                borrow = 1;
-            } else {
-                result[i] = diff as u8;
-                borrow = 0;
+        let mut result = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (diff, b1) = self.0[i].overflowing_sub(other.0[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = b1 || b2;
+        }
+        U256(result)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == U256::ZERO
+    }
+
+    pub fn mul(&self, other: &U256) -> U256 {
+        let (a, b) = (&self.0, &other.0);
+        let mut result = [0u64; 4];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 - i {
+                let cur = result[i + j] as u128 + a[i] as u128 * b[j] as u128 + carry;
+                result[i + j] = cur as u64;
+                carry = cur >> 64;
+            }
+        }
+        U256(result)
+    }
+
+    fn div_rem(&self, divisor: &U256) -> (U256, U256) {
+        if divisor.is_zero() {
+            return (U256::ZERO, U256::ZERO);
+        }
+        if self < divisor {
+            return (U256::ZERO, *self);
+        }
+        if let (Some(a), Some(b)) = (self.to_u128(), divisor.to_u128()) {
+            return (U256::from_u128(a / b), U256::from_u128(a % b));
+        }
+        if let Some(b) = divisor.to_u64() {
+            let mut quotient = [0u64; 4];
+            let mut remainder = 0u128;
+            for i in (0..4).rev() {
+                let cur = (remainder << 64) | self.0[i] as u128;
+                quotient[i] = (cur / b as u128) as u64;
+                remainder = cur % b as u128;
+            }
+            return (U256(quotient), U256::from_u128(remainder));
+        }
+        let mut quotient = U256::ZERO;
+        let mut remainder = U256::ZERO;
+        for bit in (0..256 - self.leading_zeros()).rev() {
+            remainder = remainder.shl_bits(1);
+            remainder.0[0] |= self.bit(bit) as u64;
+            if remainder >= *divisor {
+                remainder = remainder.sub(divisor);
+                quotient.0[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        (quotient, remainder)
+    }
+
+    pub fn to_u128(self) -> Option<u128> {
+        if self.0[2] != 0 || self.0[3] != 0 {
+            return None;
+        }
+        Some(((self.0[1] as u128) << 64) | self.0[0] as u128)
+    }
+
+    pub fn from_u128(val: u128) -> Self {
+        U256([val as u64, (val >> 64) as u64, 0, 0])
+    }
+
+    /// Integer division; division by zero yields zero.
+    pub fn div(&self, other: &U256) -> U256 {
+        self.div_rem(other).0
+    }
+
+    /// Modulo; modulo by zero yields zero.
+    pub fn rem(&self, other: &U256) -> U256 {
+        self.div_rem(other).1
+    }
+
+    pub fn pow(&self, exponent: &U256) -> U256 {
+        let mut result = U256::ONE;
+        let mut base = *self;
+        for bit in 0..256 - exponent.leading_zeros() {
+            if exponent.bit(bit) {
+                result = result.mul(&base);
+            }
+            base = base.mul(&base);
+        }
+        result
+    }
+
+    pub fn and(&self, other: &U256) -> U256 {
+        U256(std::array::from_fn(|i| self.0[i] & other.0[i]))
+    }
+
+    pub fn or(&self, other: &U256) -> U256 {
+        U256(std::array::from_fn(|i| self.0[i] | other.0[i]))
+    }
+
+    pub fn xor(&self, other: &U256) -> U256 {
+        U256(std::array::from_fn(|i| self.0[i] ^ other.0[i]))
+    }
+
+    pub fn not(&self) -> U256 {
+        U256(std::array::from_fn(|i| !self.0[i]))
+    }
+
+    /// Shifts left by `shift` bits; shifts of 256 or more yield zero.
+    pub fn shl(&self, shift: &U256) -> U256 {
+        match shift.to_u64() {
+            Some(bits) if bits < 256 => self.shl_bits(bits as usize),
+            _ => U256::ZERO,
+        }
+    }
+
+    /// Logical right shift; shifts of 256 or more yield zero.
+    pub fn shr(&self, shift: &U256) -> U256 {
+        match shift.to_u64() {
+            Some(bits) if bits < 256 => self.shr_bits(bits as usize),
+            _ => U256::ZERO,
+        }
+    }
+
+    /// Arithmetic right shift, filling with the sign bit.
+    pub fn sar(&self, shift: &U256) -> U256 {
+        let fill = if self.is_negative() {
+            U256::MAX
+        } else {
+            U256::ZERO
+        };
+        match shift.to_u64() {
+            Some(bits) if bits < 256 => {
+                let shifted = self.shr_bits(bits as usize);
+                if bits == 0 {
+                    shifted
+                } else {
+                    shifted.or(&fill.shl_bits(256 - bits as usize))
+                }
+            }
+            _ => fill,
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0[3] >> 63 != 0
+    }
+
+    /// Two's complement negation.
+    pub fn neg(&self) -> U256 {
+        self.not().add(&U256::ONE)
+    }
+
+    fn abs(&self) -> U256 {
+        if self.is_negative() {
+            self.neg()
+        } else {
+            *self
+        }
+    }
+
+    /// Signed division rounding toward zero; division by zero yields zero.
+    pub fn sdiv(&self, other: &U256) -> U256 {
+        let quotient = self.abs().div(&other.abs());
+        if self.is_negative() != other.is_negative() {
+            quotient.neg()
+        } else {
+            quotient
+        }
+    }
+
+    /// Signed modulo; the result takes the sign of the dividend.
+    pub fn srem(&self, other: &U256) -> U256 {
+        let remainder = self.abs().rem(&other.abs());
+        if self.is_negative() {
+            remainder.neg()
+        } else {
+            remainder
+        }
+    }
+
+    pub fn slt(&self, other: &U256) -> bool {
+        match (self.is_negative(), other.is_negative()) {
+            (true, false) => true,
+            (false, true) => false,
+            _ => self < other,
+        }
+    }
+
+    pub fn sgt(&self, other: &U256) -> bool {
+        other.slt(self)
+    }
+
+    /// Extends the sign of the `byte_index`-th lowest byte (0 = least
+    /// significant) through the whole word.
+    pub fn sign_extend(&self, byte_index: &U256) -> U256 {
+        match byte_index.to_u64() {
+            Some(b) if b < 31 => {
+                let bit = b as usize * 8 + 7;
+                let mask = U256::ONE.shl_bits(bit + 1).sub(&U256::ONE);
+                if self.bit(bit) {
+                    self.or(&mask.not())
+                } else {
+                    self.and(&mask)
+                }
+            }
+            _ => *self,
+        }
+    }
+
+    /// Number of significant bytes, as priced by EXP.
+    pub fn byte_len(&self) -> usize {
+        (256 - self.leading_zeros()).div_ceil(8)
+    }
+
+    fn leading_zeros(&self) -> usize {
+        for i in (0..4).rev() {
+            if self.0[i] != 0 {
+                return (3 - i) * 64 + self.0[i].leading_zeros() as usize;
+            }
+        }
+        256
+    }
+
+    fn bit(&self, bit: usize) -> bool {
+        self.0[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    fn shl_bits(&self, bits: usize) -> U256 {
+        let (words, offset) = (bits / 64, bits % 64);
+        let mut result = [0u64; 4];
+        for (i, limb) in result.iter_mut().enumerate().skip(words) {
+            *limb = self.0[i - words] << offset;
+            if offset > 0 && i > words {
+                *limb |= self.0[i - words - 1] >> (64 - offset);
             }
         }
         U256(result)
     }
+
+    fn shr_bits(&self, bits: usize) -> U256 {
+        let (words, offset) = (bits / 64, bits % 64);
+        let mut result = [0u64; 4];
+        for (i, limb) in result.iter_mut().enumerate().take(4 - words) {
+            *limb = self.0[i + words] >> offset;
+            if offset > 0 && i + words + 1 < 4 {
+                *limb |= self.0[i + words + 1] << (64 - offset);
+            }
+        }
+        U256(result)
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl From<bool> for U256 {
+    fn from(value: bool) -> Self {
+        if value {
+            U256::ONE
+        } else {
+            U256::ZERO
+        }
+    }
+}
+
+// Serialized as the big-endian byte array so existing block files still load.
+impl Serialize for U256 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_newtype_struct("U256", &self.to_be_bytes())
+    }
+}
+
+impl<'de> Deserialize<'de> for U256 {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct U256Helper([u8; 32]);
+
+        let helper = U256Helper::deserialize(deserializer)?;
+        Ok(U256::from_be_bytes(helper.0))
+    }
+}
+
+impl fmt::Debug for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "U256({})", self)
+    }
 }
 
 impl fmt::Display for U256 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "0x{}", hex::encode(&self.0))
+        write!(f, "0x{}", hex::encode(&self.to_be_bytes()))
+    }
+}
+
+/// Two-operand word operations. `apply(a, b)` computes `a op b`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    SDiv,
+    Mod,
+    SMod,
+    Exp,
+    /// Sign-extends `a` from byte `b`.
+    SignExtend,
+    Lt,
+    Gt,
+    SLt,
+    SGt,
+    Eq,
+    And,
+    Or,
+    Xor,
+    /// `a << b`
+    Shl,
+    /// `a >> b`
+    Shr,
+    Sar,
+}
+
+impl BinaryOp {
+    pub fn apply(&self, a: &U256, b: &U256) -> U256 {
+        match self {
+            BinaryOp::Add => a.add(b),
+            BinaryOp::Sub => a.sub(b),
+            BinaryOp::Mul => a.mul(b),
+            BinaryOp::Div => a.div(b),
+            BinaryOp::SDiv => a.sdiv(b),
+            BinaryOp::Mod => a.rem(b),
+            BinaryOp::SMod => a.srem(b),
+            BinaryOp::Exp => a.pow(b),
+            BinaryOp::SignExtend => a.sign_extend(b),
+            BinaryOp::Lt => (a < b).into(),
+            BinaryOp::Gt => (a > b).into(),
+            BinaryOp::SLt => a.slt(b).into(),
+            BinaryOp::SGt => a.sgt(b).into(),
+            BinaryOp::Eq => (a == b).into(),
+            BinaryOp::And => a.and(b),
+            BinaryOp::Or => a.or(b),
+            BinaryOp::Xor => a.xor(b),
+            BinaryOp::Shl => a.shl(b),
+            BinaryOp::Shr => a.shr(b),
+            BinaryOp::Sar => a.sar(b),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "ADD",
+            BinaryOp::Sub => "SUB",
+            BinaryOp::Mul => "MUL",
+            BinaryOp::Div => "DIV",
+            BinaryOp::SDiv => "SDIV",
+            BinaryOp::Mod => "MOD",
+            BinaryOp::SMod => "SMOD",
+            BinaryOp::Exp => "EXP",
+            BinaryOp::SignExtend => "SIGNEXTEND",
+            BinaryOp::Lt => "LT",
+            BinaryOp::Gt => "GT",
+            BinaryOp::SLt => "SLT",
+            BinaryOp::SGt => "SGT",
+            BinaryOp::Eq => "EQ",
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+            BinaryOp::Xor => "XOR",
+            BinaryOp::Shl => "SHL",
+            BinaryOp::Shr => "SHR",
+            BinaryOp::Sar => "SAR",
+        }
     }
 }
 
+/// Ops carrying a `U256` pop the stack top `a` and push `a op immediate`.
+/// `Binary` takes the right-hand operand from the stack instead, so
+/// `Push(x), Binary(op)` behaves like the immediate form with `x`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MicroOp {
     SLoad(Key),
     SStore(Key, U256),
+    /// Pops the value to store.
+    SStoreTop(Key),
+    /// Pops the slot, then the value, and stores at `(address, slot)`.
+    SStoreTopAt([u8; 20]),
+    /// Transient storage (EIP-1153): not persisted past the transaction
+    /// and not part of the access sets.
+    TLoad(Key),
+    TStore(Key, U256),
+    /// Pops the value to store transiently.
+    TStoreTop(Key),
+    /// Pops the slot, then the address, and pushes the stored value.
+    SLoadDyn,
+    /// Pops the slot, then the address, then the value to store.
+    SStoreDyn,
+    /// Pops the base slot, then the mapping key, and pushes
+    /// `keccak(key . slot)`, the slot of `mapping[key]`.
+    MapSlot,
+    /// Moves `amount` wei from the executing account to `to`; reverts if
+    /// the account cannot cover it.
+    Transfer([u8; 20], U256),
+    /// Pops the gas, address, value, argument offset and size, runs the
+    /// code at the address with that memory range as calldata, and pushes
+    /// 1 on success, 0 otherwise.
+    Call,
+    /// Like `Call` without the value; the callee cannot modify state.
+    StaticCall,
+    /// Pops a byte offset and pushes the calldata word there, zero-padded.
+    CallDataLoad,
+    CallDataSize,
+    /// Pushes the account that called the running frame; the sender at
+    /// the top level.
+    Caller,
+    /// Pops an address and pushes its balance.
+    Balance,
+    /// Block values, the same for every transaction of the block.
+    Number,
+    Timestamp,
+    Coinbase,
+    ChainId,
+    BaseFee,
+    /// Pops the value and deploys the code at an address derived from the
+    /// executing account and its nonce. Pushes the address, or 0 if the
+    /// deployment failed.
+    Create(Vec<MicroOp>),
+    /// Like `Create`, then pops a salt and derives the address from it and
+    /// the code hash instead of the nonce.
+    Create2(Vec<MicroOp>),
+    /// Pop a memory offset and size, then zero to four topics, and emit a
+    /// log of that memory range from the executing account.
+    Log0,
+    Log1,
+    Log2,
+    Log3,
+    Log4,
     Add(U256),
     Sub(U256),
+    Mul(U256),
+    Div(U256),
+    SDiv(U256),
+    Mod(U256),
+    SMod(U256),
+    Exp(U256),
+    /// Sign-extends the stack top from the byte the immediate indexes, like
+    /// SIGNEXTEND with the byte index on top: the immediate is the index,
+    /// not the value.
+    SignExtend(U256),
+    Lt(U256),
+    Gt(U256),
+    SLt(U256),
+    SGt(U256),
+    Eq(U256),
+    IsZero,
+    And(U256),
+    Or(U256),
+    Xor(U256),
+    Not,
+    Shl(U256),
+    Shr(U256),
+    Sar(U256),
+    /// Pops `b` then `a`, pushes `a op b`.
+    Binary(BinaryOp),
+    Push(U256),
+    Pop,
+    /// Duplicates the n-th stack item (1 = top), n in 1..=16.
+    Dup(u8),
+    /// Swaps the top with the (n+1)-th stack item, n in 1..=16.
+    Swap(u8),
+    /// Hashes the inline bytes.
     Keccak(Vec<u8>),
+    /// Pops the offset and pushes the 32-byte word read from memory there.
+    MLoad,
+    /// Pops the offset, then the value, and writes it as a 32-byte word.
+    MStore,
+    /// Pops the offset, then the value, and writes its lowest byte.
+    MStore8,
+    /// Pushes the memory size in bytes.
+    MSize,
+    /// Pops the offset, then the size, and hashes that memory range.
+    KeccakMem,
+    /// Jumps to the op at this index, which must be a `JumpDest`.
+    Jump(usize),
+    /// Pops the condition and jumps if it is non-zero.
+    JumpI(usize),
+    JumpDest,
+    /// Halts successfully.
+    Stop,
+    /// Halts and rolls back all storage writes of the current call.
+    Revert,
     NoOp,
 }
 
+impl MicroOp {
+    /// The binary op and its immediate operand for the arithmetic forms;
+    /// `Binary` has no immediate.
+    pub fn as_binary(&self) -> Option<(BinaryOp, Option<U256>)> {
+        let (op, value) = match self {
+            MicroOp::Add(v) => (BinaryOp::Add, v),
+            MicroOp::Sub(v) => (BinaryOp::Sub, v),
+            MicroOp::Mul(v) => (BinaryOp::Mul, v),
+            MicroOp::Div(v) => (BinaryOp::Div, v),
+            MicroOp::SDiv(v) => (BinaryOp::SDiv, v),
+            MicroOp::Mod(v) => (BinaryOp::Mod, v),
+            MicroOp::SMod(v) => (BinaryOp::SMod, v),
+            MicroOp::Exp(v) => (BinaryOp::Exp, v),
+            MicroOp::SignExtend(v) => (BinaryOp::SignExtend, v),
+            MicroOp::Lt(v) => (BinaryOp::Lt, v),
+            MicroOp::Gt(v) => (BinaryOp::Gt, v),
+            MicroOp::SLt(v) => (BinaryOp::SLt, v),
+            MicroOp::SGt(v) => (BinaryOp::SGt, v),
+            MicroOp::Eq(v) => (BinaryOp::Eq, v),
+            MicroOp::And(v) => (BinaryOp::And, v),
+            MicroOp::Or(v) => (BinaryOp::Or, v),
+            MicroOp::Xor(v) => (BinaryOp::Xor, v),
+            MicroOp::Shl(v) => (BinaryOp::Shl, v),
+            MicroOp::Shr(v) => (BinaryOp::Shr, v),
+            MicroOp::Sar(v) => (BinaryOp::Sar, v),
+            MicroOp::Binary(op) => return Some((*op, None)),
+            _ => return None,
+        };
+        Some((op, Some(*value)))
+    }
+
+    /// Number of topics popped by a `Log` op.
+    pub fn log_topics(&self) -> Option<usize> {
+        match self {
+            MicroOp::Log0 => Some(0),
+            MicroOp::Log1 => Some(1),
+            MicroOp::Log2 => Some(2),
+            MicroOp::Log3 => Some(3),
+            MicroOp::Log4 => Some(4),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     pub id: u64,
@@ -105,13 +701,28 @@ pub struct Transaction {
     pub metadata: TransactionMetadata,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TransactionMetadata {
     pub program: Vec<MicroOp>,
+    /// Keys warmed up front. When not empty, the program may touch no
+    /// other key.
     pub access_list: Vec<Key>,
     pub blob_size: u64,
     pub nonce: u64,
     pub from: [u8; 20],
+    /// EIP-1559 fee caps, in wei per gas.
+    #[serde(default)]
+    pub max_fee_per_gas: U256,
+    #[serde(default)]
+    pub max_priority_fee_per_gas: U256,
+    /// Contract to call instead of running `program`.
+    #[serde(default)]
+    pub to: Option<[u8; 20]>,
+    /// Wei sent along to `to`.
+    #[serde(default)]
+    pub value: U256,
+    #[serde(default)]
+    pub calldata: Vec<u8>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -120,6 +731,29 @@ pub struct Block {
     pub timestamp: u64,
     pub transactions: Vec<Transaction>,
     pub parent_hash: [u8; 32],
+    /// Cap on the gas used by included transactions; unlimited when absent.
+    #[serde(default = "unlimited_gas")]
+    pub gas_limit: u64,
+    /// EIP-4844 blob gas above target accumulated by earlier blocks; sets
+    /// the blob base fee.
+    #[serde(default)]
+    pub excess_blob_gas: u64,
+    /// Beneficiary of the priority fees.
+    #[serde(default)]
+    pub coinbase: [u8; 20],
+    /// EIP-1559 base fee per gas, burned.
+    #[serde(default)]
+    pub base_fee: U256,
+    #[serde(default = "mainnet")]
+    pub chain_id: u64,
+}
+
+fn unlimited_gas() -> u64 {
+    u64::MAX
+}
+
+fn mainnet() -> u64 {
+    1
 }
 
 impl Block {
@@ -129,14 +763,66 @@ impl Block {
             timestamp: chrono::Utc::now().timestamp() as u64,
             transactions,
             parent_hash: [0u8; 32],
+            gas_limit: unlimited_gas(),
+            excess_blob_gas: 0,
+            coinbase: [0u8; 20],
+            base_fee: U256::ZERO,
+            chain_id: mainnet(),
         }
     }
+
+    pub fn with_gas_limit(mut self, gas_limit: u64) -> Self {
+        self.gas_limit = gas_limit;
+        self
+    }
+
+    pub fn with_excess_blob_gas(mut self, excess_blob_gas: u64) -> Self {
+        self.excess_blob_gas = excess_blob_gas;
+        self
+    }
+
+    pub fn with_coinbase(mut self, coinbase: [u8; 20]) -> Self {
+        self.coinbase = coinbase;
+        self
+    }
+
+    pub fn with_base_fee(mut self, base_fee: U256) -> Self {
+        self.base_fee = base_fee;
+        self
+    }
+
+    pub fn with_chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.number.to_be_bytes());
+        hasher.update(&self.timestamp.to_be_bytes());
+        hasher.update(&self.parent_hash);
+        hasher.update(&self.gas_limit.to_be_bytes());
+        hasher.update(&self.excess_blob_gas.to_be_bytes());
+        hasher.update(&self.coinbase);
+        hasher.update(&self.base_fee.to_be_bytes());
+        hasher.update(&self.chain_id.to_be_bytes());
+        // Every field, so that the hash commits to what the block runs.
+        for tx in &self.transactions {
+            let encoded = serde_json::to_vec(tx).expect("transactions serialize");
+            hasher.update(&(encoded.len() as u64).to_be_bytes());
+            hasher.update(&encoded);
+        }
+        *hasher.finalize().as_bytes()
+    }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct AccessSets {
     pub reads: AHashSet<Key>,
     pub writes: AHashSet<Key>,
+    /// Set by oracles when the program touches keys that cannot be resolved
+    /// before execution. Such a transaction conflicts with every other one.
+    pub unknown: bool,
 }
 
 impl Serialize for AccessSets {
@@ -145,11 +831,12 @@ impl Serialize for AccessSets {
         S: serde::Serializer,
     {
         use serde::ser::SerializeStruct;
-        let mut state = serializer.serialize_struct("AccessSets", 2)?;
+        let mut state = serializer.serialize_struct("AccessSets", 3)?;
         let reads_vec: Vec<Key> = self.reads.iter().copied().collect();
         let writes_vec: Vec<Key> = self.writes.iter().copied().collect();
         state.serialize_field("reads", &reads_vec)?;
         state.serialize_field("writes", &writes_vec)?;
+        state.serialize_field("unknown", &self.unknown)?;
         state.end()
     }
 }
@@ -163,12 +850,15 @@ impl<'de> Deserialize<'de> for AccessSets {
         struct AccessSetsHelper {
             reads: Vec<Key>,
             writes: Vec<Key>,
+            #[serde(default)]
+            unknown: bool,
         }
 
         let helper = AccessSetsHelper::deserialize(deserializer)?;
         Ok(AccessSets {
             reads: helper.reads.into_iter().collect(),
             writes: helper.writes.into_iter().collect(),
+            unknown: helper.unknown,
         })
     }
 }
@@ -189,25 +879,243 @@ impl AccessSets {
     pub fn merge(&mut self, other: &AccessSets) {
         self.reads.extend(&other.reads);
         self.writes.extend(&other.writes);
+        self.unknown |= other.unknown;
     }
 
     pub fn has_conflict_with(&self, other: &AccessSets) -> bool {
-        !self.writes.is_disjoint(&other.writes)
+        self.unknown
+            || other.unknown
+            || !self.writes.is_disjoint(&other.writes)
             || !self.writes.is_disjoint(&other.reads)
             || !self.reads.is_disjoint(&other.writes)
     }
 }
 
+/// Event emitted by a `Log` op. Logs of a reverted frame are discarded
+/// with its writes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Log {
+    pub address: [u8; 20],
+    pub topics: Vec<U256>,
+    pub data: Vec<u8>,
+}
+
+/// Why a transaction failed. Errors raised while running code come wrapped
+/// in [`ExecutionError::At`] with the op that raised them; the others make
+/// the transaction invalid before it runs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExecutionError {
+    OutOfGas {
+        used: u64,
+        limit: u64,
+    },
+    /// Raised by the named op.
+    StackUnderflow(String),
+    StackOverflow,
+    InvalidJump(usize),
+    /// `DUP` or `SWAP` outside 1..=16.
+    InvalidOperand(String),
+    MemoryOutOfRange,
+    /// A state-changing op inside a static call.
+    StaticStateChange(String),
+    /// A storage op on a slot holding account state.
+    ReservedSlot(Key),
+    /// An op the gas schedule does not price.
+    UnsupportedOp(String),
+    /// Data returned by a reverting frame.
+    Revert(Vec<u8>),
+    NonceMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    /// A key outside the access list a transaction committed to.
+    UndeclaredAccess(Key),
+    IntrinsicGas {
+        required: u64,
+        limit: u64,
+    },
+    FeeCapBelowBaseFee {
+        max_fee: U256,
+        base_fee: U256,
+    },
+    ValueWithoutRecipient,
+    InsufficientBalance {
+        balance: U256,
+        gas_cost: U256,
+        value: U256,
+    },
+    BlockGasLimit {
+        gas_limit: u64,
+        remaining: u64,
+    },
+    BlobsUnsupported,
+    BlobGasLimit {
+        blob_gas: u64,
+        remaining: u64,
+    },
+    /// `error` raised by op `pc` of transaction `tx_id`.
+    At {
+        tx_id: u64,
+        pc: usize,
+        error: Box<ExecutionError>,
+    },
+}
+
+impl ExecutionError {
+    pub fn at(self, tx_id: u64, pc: usize) -> Self {
+        Self::At {
+            tx_id,
+            pc,
+            error: Box::new(self),
+        }
+    }
+
+    /// The error itself, without where it was raised.
+    pub fn cause(&self) -> &ExecutionError {
+        match self {
+            Self::At { error, .. } => error.cause(),
+            error => error,
+        }
+    }
+
+    /// Name of the variant, for counting failures by kind.
+    pub fn kind(&self) -> &'static str {
+        match self.cause() {
+            Self::OutOfGas { .. } => "OutOfGas",
+            Self::StackUnderflow(_) => "StackUnderflow",
+            Self::StackOverflow => "StackOverflow",
+            Self::InvalidJump(_) => "InvalidJump",
+            Self::InvalidOperand(_) => "InvalidOperand",
+            Self::MemoryOutOfRange => "MemoryOutOfRange",
+            Self::StaticStateChange(_) => "StaticStateChange",
+            Self::ReservedSlot(_) => "ReservedSlot",
+            Self::UnsupportedOp(_) => "UnsupportedOp",
+            Self::Revert(_) => "Revert",
+            Self::NonceMismatch { .. } => "NonceMismatch",
+            Self::UndeclaredAccess(_) => "UndeclaredAccess",
+            Self::IntrinsicGas { .. } => "IntrinsicGas",
+            Self::FeeCapBelowBaseFee { .. } => "FeeCapBelowBaseFee",
+            Self::ValueWithoutRecipient => "ValueWithoutRecipient",
+            Self::InsufficientBalance { .. } => "InsufficientBalance",
+            Self::BlockGasLimit { .. } => "BlockGasLimit",
+            Self::BlobsUnsupported => "BlobsUnsupported",
+            Self::BlobGasLimit { .. } => "BlobGasLimit",
+            Self::At { .. } => unreachable!(),
+        }
+    }
+
+    /// Whether the transaction was left out of the block rather than run.
+    pub fn is_rejection(&self) -> bool {
+        matches!(
+            self.cause(),
+            Self::BlockGasLimit { .. } | Self::BlobsUnsupported | Self::BlobGasLimit { .. }
+        )
+    }
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfGas { used, limit } => {
+                write!(f, "Out of gas: used {} > limit {}", used, limit)
+            }
+            Self::StackUnderflow(op) => write!(f, "Stack underflow in {}", op),
+            Self::StackOverflow => write!(f, "Stack overflow"),
+            Self::InvalidJump(target) => write!(f, "Invalid jump to {}", target),
+            Self::InvalidOperand(op) => write!(f, "Invalid {}", op),
+            Self::MemoryOutOfRange => write!(f, "Memory access out of range"),
+            Self::StaticStateChange(op) => write!(f, "{} in a static call", op),
+            Self::ReservedSlot(key) => write!(f, "Slot {} is reserved for account state", key),
+            Self::UnsupportedOp(op) => write!(f, "{} is not available in this gas schedule", op),
+            Self::Revert(data) if data.is_empty() => write!(f, "Reverted"),
+            Self::Revert(data) => write!(f, "Reverted with 0x{}", hex::encode(data)),
+            Self::NonceMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "Nonce {} does not match account nonce {}",
+                    actual, expected
+                )
+            }
+            Self::UndeclaredAccess(key) => write!(f, "Access to undeclared key {}", key),
+            Self::IntrinsicGas { required, limit } => {
+                write!(f, "Intrinsic gas {} exceeds gas limit {}", required, limit)
+            }
+            Self::FeeCapBelowBaseFee { max_fee, base_fee } => write!(
+                f,
+                "Max fee per gas {} is below base fee {}",
+                max_fee, base_fee
+            ),
+            Self::ValueWithoutRecipient => write!(f, "Value sent without a recipient"),
+            Self::InsufficientBalance {
+                balance,
+                gas_cost,
+                value,
+            } => write!(
+                f,
+                "Balance {} cannot cover gas cost {} and value {}",
+                balance, gas_cost, value
+            ),
+            Self::BlockGasLimit {
+                gas_limit,
+                remaining,
+            } => write!(
+                f,
+                "Gas limit {} exceeds remaining block gas {}",
+                gas_limit, remaining
+            ),
+            Self::BlobsUnsupported => {
+                write!(
+                    f,
+                    "Blob transactions are not supported by this gas schedule"
+                )
+            }
+            Self::BlobGasLimit {
+                blob_gas,
+                remaining,
+            } => write!(
+                f,
+                "Blob gas {} exceeds remaining block blob gas {}",
+                blob_gas, remaining
+            ),
+            Self::At { tx_id, pc, error } => {
+                write!(f, "Transaction {} failed at op {}: {}", tx_id, pc, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+/// Number of failed transactions per [`ExecutionError::kind`].
+pub fn count_failures(results: &[ExecutionResult]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for error in results.iter().filter_map(|r| r.error.as_ref()) {
+        *counts.entry(error.kind().to_string()).or_insert(0) += 1;
+    }
+    counts
+}
+
 #[derive(Debug, Clone)]
 pub struct ExecutionResult {
     pub tx_id: u64,
     pub success: bool,
+    /// Net gas charged: `gross_gas - gas_refund`.
     pub gas_used: u64,
+    pub gross_gas: u64,
+    pub gas_refund: u64,
     pub access_sets: AccessSets,
     pub warm_keys: AHashSet<Key>,
     pub cold_keys: AHashSet<Key>,
     pub reverted: bool,
-    pub error: Option<String>,
+    pub error: Option<ExecutionError>,
+    /// EIP-4844 blob gas and the fee paid for it, charged whatever the
+    /// outcome of execution.
+    pub blob_gas_used: u64,
+    pub blob_fee: U256,
+    /// Owed to the coinbase. Executors may credit it after the fact.
+    pub priority_fee: U256,
+    /// Emitted by a successful transaction, in order.
+    pub logs: Vec<Log>,
 }
 
 impl ExecutionResult {
@@ -222,24 +1130,91 @@ impl ExecutionResult {
             tx_id,
             success: true,
             gas_used,
+            gross_gas: gas_used,
+            gas_refund: 0,
             access_sets,
             warm_keys,
             cold_keys,
             reverted: false,
             error: None,
+            blob_gas_used: 0,
+            blob_fee: U256::ZERO,
+            priority_fee: U256::ZERO,
+            logs: Vec::new(),
+        }
+    }
+
+    /// The transaction ran but its writes were rolled back. Reads are kept
+    /// since the outcome still depends on them.
+    pub fn reverted(
+        tx_id: u64,
+        gas_used: u64,
+        access_sets: AccessSets,
+        warm_keys: AHashSet<Key>,
+        cold_keys: AHashSet<Key>,
+        error: ExecutionError,
+    ) -> Self {
+        Self {
+            tx_id,
+            success: false,
+            gas_used,
+            gross_gas: gas_used,
+            gas_refund: 0,
+            access_sets: AccessSets {
+                reads: access_sets.reads,
+                writes: AHashSet::new(),
+                unknown: false,
+            },
+            warm_keys,
+            cold_keys,
+            reverted: true,
+            error: Some(error),
+            blob_gas_used: 0,
+            blob_fee: U256::ZERO,
+            priority_fee: U256::ZERO,
+            logs: Vec::new(),
         }
     }
 
-    pub fn failure(tx_id: u64, error: String) -> Self {
+    pub fn with_logs(mut self, logs: Vec<Log>) -> Self {
+        self.logs = logs;
+        self
+    }
+
+    pub fn with_priority_fee(mut self, priority_fee: U256) -> Self {
+        self.priority_fee = priority_fee;
+        self
+    }
+
+    pub fn with_blob_gas(mut self, blob_gas_used: u64, blob_fee: U256) -> Self {
+        self.blob_gas_used = blob_gas_used;
+        self.blob_fee = blob_fee;
+        self
+    }
+
+    /// Pays back `refund` out of the gross gas.
+    pub fn with_refund(mut self, refund: u64) -> Self {
+        self.gas_refund = refund.min(self.gross_gas);
+        self.gas_used = self.gross_gas - self.gas_refund;
+        self
+    }
+
+    pub fn failure(tx_id: u64, error: ExecutionError) -> Self {
         Self {
             tx_id,
             success: false,
             gas_used: 0,
+            gross_gas: 0,
+            gas_refund: 0,
             access_sets: AccessSets::new(),
             warm_keys: AHashSet::new(),
             cold_keys: AHashSet::new(),
             reverted: true,
             error: Some(error),
+            blob_gas_used: 0,
+            blob_fee: U256::ZERO,
+            priority_fee: U256::ZERO,
+            logs: Vec::new(),
         }
     }
 }
@@ -258,6 +1233,12 @@ pub struct Metrics {
     pub tx_latency_p95: f64,
     pub tx_latency_p99: f64,
     pub iops: f64,
+    pub blob_gas_used: u64,
+    pub blob_fees: U256,
+    pub priority_fees: U256,
+    /// Failed transactions per error kind.
+    #[serde(default)]
+    pub failures: BTreeMap<String, usize>,
 }
 
 impl Default for Metrics {
@@ -275,6 +1256,10 @@ impl Default for Metrics {
             tx_latency_p95: 0.0,
             tx_latency_p99: 0.0,
             iops: 0.0,
+            blob_gas_used: 0,
+            blob_fees: U256::ZERO,
+            priority_fees: U256::ZERO,
+            failures: BTreeMap::new(),
         }
     }
 }
@@ -301,4 +1286,84 @@ mod tests {
         set2.add_read(Key::new([1u8; 20], [1u8; 32]));
         assert!(set1.has_conflict_with(&set2));
     }
+
+    #[test]
+    fn test_block_hash_commits_to_transactions() {
+        let tx = Transaction {
+            id: 1,
+            reads: vec![],
+            writes: vec![],
+            gas_hint: 50_000,
+            metadata: TransactionMetadata {
+                program: vec![MicroOp::Push(U256::ONE)],
+                from: [1u8; 20],
+                ..Default::default()
+            },
+        };
+        let block = Block::new(1, vec![tx.clone()]);
+        let hash = block.hash();
+        assert_eq!(hash, block.clone().hash());
+
+        let mut changed = block.clone();
+        changed.transactions[0].metadata.program.push(MicroOp::Pop);
+        assert_ne!(changed.hash(), hash);
+        let mut changed = block.clone();
+        changed.transactions[0].metadata.to = Some([2u8; 20]);
+        assert_ne!(changed.hash(), hash);
+        let mut changed = block;
+        changed.transactions[0].metadata.calldata = vec![1];
+        assert_ne!(changed.hash(), hash);
+    }
+
+    #[test]
+    fn test_u256_arithmetic() {
+        let n = U256::from_u64;
+        let minus = |v: u64| n(v).neg();
+
+        assert_eq!(n(7).mul(&n(6)), n(42));
+        assert_eq!(U256::MAX.mul(&n(2)), U256::MAX.sub(&U256::ONE));
+        assert_eq!(n(43).div(&n(6)), n(7));
+        assert_eq!(n(43).rem(&n(6)), n(1));
+        assert_eq!(n(43).div(&U256::ZERO), U256::ZERO);
+        assert_eq!(n(43).rem(&U256::ZERO), U256::ZERO);
+        let big = U256::MAX.sub(&n(4));
+        assert_eq!(big.div(&n(3)).mul(&n(3)).add(&big.rem(&n(3))), big);
+        assert_eq!(big.div(&big.shr(&n(1))), n(2));
+        assert_eq!(n(3).pow(&n(4)), n(81));
+        assert_eq!(n(2).pow(&n(256)), U256::ZERO);
+        assert_eq!(n(0).pow(&n(0)), U256::ONE);
+
+        assert_eq!(minus(7).sdiv(&n(2)), minus(3));
+        assert_eq!(minus(7).srem(&n(2)), minus(1));
+        assert_eq!(n(7).srem(&minus(2)), n(1));
+        let min = U256::ONE.shl(&n(255));
+        assert_eq!(min.sdiv(&U256::MAX), min);
+        assert!(minus(1).slt(&n(0)));
+        assert!(n(1).sgt(&minus(5)));
+        assert!(minus(1) > n(0));
+
+        assert_eq!(n(1).shl(&n(64)).shr(&n(64)), n(1));
+        assert_eq!(n(1).shl(&n(256)), U256::ZERO);
+        assert_eq!(minus(16).sar(&n(2)), minus(4));
+        assert_eq!(minus(1).sar(&n(300)), U256::MAX);
+        assert_eq!(n(0xff).sign_extend(&U256::ZERO), U256::MAX);
+        assert_eq!(n(0x7f).sign_extend(&U256::ZERO), n(0x7f));
+        assert_eq!(n(0x1ff).byte_len(), 2);
+    }
+
+    #[test]
+    fn test_u256_byte_compat() {
+        let mut bytes = [0u8; 32];
+        bytes[0] = 0x80;
+        bytes[23] = 1;
+        bytes[31] = 2;
+        let value = U256::from_be_bytes(bytes);
+        assert_eq!(value.to_be_bytes(), bytes);
+        assert_eq!(value.as_limbs(), &[2, 1, 0, 1 << 63]);
+        assert!(value > U256::from_u64(u64::MAX));
+
+        let legacy = serde_json::to_string(&bytes).unwrap();
+        assert_eq!(serde_json::to_string(&value).unwrap(), legacy);
+        assert_eq!(serde_json::from_str::<U256>(&legacy).unwrap(), value);
+    }
 }